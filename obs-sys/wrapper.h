@@ -1 +1,3 @@
 #include <obs/obs-module.h>
+#include <obs/util/platform.h>
+#include <obs/graphics/image-file.h>
@@ -0,0 +1,28 @@
+extern crate wayland_scanner;
+
+use std::env;
+use std::path::PathBuf;
+use wayland_scanner::Side;
+
+/// Protocols in `protocols/` that `wayland-protocols` does not ship (yet), or only ships in an
+/// older version
+const PROTOCOLS: &'static [&'static str] = &[
+    "ext-foreign-toplevel-list-v1",
+    "ext-image-capture-source-v1",
+    "ext-image-copy-capture-v1",
+    "wlr-screencopy-unstable-v1",
+];
+
+fn main() {
+    let out_path: PathBuf = env::var("OUT_DIR").unwrap().into();
+
+    for name in PROTOCOLS {
+        let xml = format!("protocols/{}.xml", name);
+        println!("cargo:rerun-if-changed={}", xml);
+        wayland_scanner::generate_code(
+            &xml,
+            out_path.join(format!("{}_client_api.rs", name.replace('-', "_"))),
+            Side::Client
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::slice;
+use ::obs::sys as obs_sys;
+use wayland_client::protocol::wl_output::Transform;
+use crate::region::Region;
+
+/// A BGRA image that captured frames are scaled and copied into
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Canvas {
+    /// Opaque black canvas
+    pub fn new(width: u32, height: u32) -> Canvas {
+        let mut data = vec![0u8; (width as usize) * (height as usize) * 4];
+        for px in data.chunks_mut(4) {
+            px[3] = 0xff;
+        }
+        Canvas {
+            width: width,
+            height: height,
+            data: data,
+        }
+    }
+
+    /// Sets every pixel to `bgra`
+    pub fn fill(&mut self, bgra: [u8; 4]) {
+        for px in self.data.chunks_mut(4) {
+            px.copy_from_slice(&bgra);
+        }
+    }
+
+    #[inline(always)]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[inline(always)]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    #[inline(always)]
+    pub fn stride(&self) -> u32 {
+        self.width * 4
+    }
+
+    #[inline(always)]
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    #[inline(always)]
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Copies `area` of the canvas into `dst`, which has the same layout
+    pub fn copy_to(&self, dst: &mut [u8], area: &Region) {
+        let bounds = Region::new(0, 0, self.width as i32, self.height as i32);
+        let area = match area.intersect(&bounds) {
+            Some(a) => a,
+            None => return,
+        };
+        let stride = self.stride() as usize;
+        let len = (area.width as usize) * 4;
+        for y in area.y..(area.y + area.height) {
+            let start = (y as usize) * stride + (area.x as usize) * 4;
+            dst[start..(start + len)].copy_from_slice(&self.data[start..(start + len)]);
+        }
+    }
+
+    /// Scales `frame` to `dst` (nearest neighbour) and copies it over the canvas, undoing the
+    /// `transform` its contents were captured with. Returns the part of the canvas that changed, or
+    /// `None` if `frame` is in a format that can't be copied.
+    #[inline(always)]
+    pub fn blit(&mut self, frame: &obs_sys::obs_source_frame, dst: &Region, transform: Transform) -> Option<Region> {
+        self.blit_within(frame, dst, dst, transform)
+    }
+
+    /// Like `blit`, but only touches the canvas pixels within `area`
+    pub fn blit_within(&mut self, frame: &obs_sys::obs_source_frame, dst: &Region, area: &Region, transform: Transform) -> Option<Region> {
+        use obs_sys::video_format::*;
+
+        // Byte indices of blue, green, red and alpha (if any) within a source pixel
+        let (b, g, r, a) = match frame.format {
+            VIDEO_FORMAT_BGRA => (0, 1, 2, Some(3)),
+            VIDEO_FORMAT_BGRX => (0, 1, 2, None),
+            VIDEO_FORMAT_RGBA => (2, 1, 0, Some(3)),
+            _ => return None,
+        };
+        let bounds = Region::new(0, 0, self.width as i32, self.height as i32);
+        let clipped = dst.intersect(area)?.intersect(&bounds)?;
+        if frame.width == 0 || frame.height == 0 {
+            return None;
+        }
+        let (width, height) = upright_size(transform, frame.width, frame.height);
+        let stride = self.stride() as usize;
+        for y in clipped.y..(clipped.y + clipped.height) {
+            let uy = (((y - dst.y) as u64) * (height as u64) / (dst.height as u64)) as u32;
+            let dst_start = (y as usize) * stride + (clipped.x as usize) * 4;
+            let dst_row = &mut self.data[dst_start..(dst_start + (clipped.width as usize) * 4)];
+            for (i, d) in dst_row.chunks_mut(4).enumerate() {
+                let x = clipped.x + i as i32;
+                let ux = (((x - dst.x) as u64) * (width as u64) / (dst.width as u64)) as u32;
+                let (sx, mut sy) = buffer_position(transform, ux, uy, width, height);
+                if frame.flip {
+                    sy = frame.height - 1 - sy;
+                }
+                let s = unsafe {
+                    let px = frame.data[0].offset((sy as isize) * (frame.linesize[0] as isize) + (sx as isize) * 4);
+                    slice::from_raw_parts(px, 4)
+                };
+                d[0] = s[b];
+                d[1] = s[g];
+                d[2] = s[r];
+                d[3] = a.map(|a| s[a]).unwrap_or(0xff);
+            }
+        }
+        Some(clipped)
+    }
+}
+
+/// Size of the upright image in a `width`x`height` buffer whose contents have `transform` applied
+pub fn upright_size(transform: Transform, width: u32, height: u32) -> (u32, u32) {
+    match transform {
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => (height, width),
+        _ => (width, height),
+    }
+}
+
+/// Where pixel `(x, y)` of a `width`x`height` upright image is found in a buffer whose contents have
+/// `transform` applied. Rotations are counter-clockwise, flips are around the vertical axis and
+/// come before the rotation, as in wl_output.
+pub fn buffer_position(transform: Transform, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+    let (right, bottom) = (width - 1 - x, height - 1 - y);
+    match transform {
+        Transform::Normal => (x, y),
+        Transform::_90 => (y, right),
+        Transform::_180 => (right, bottom),
+        Transform::_270 => (bottom, x),
+        Transform::Flipped => (right, y),
+        Transform::Flipped90 => (y, x),
+        Transform::Flipped180 => (x, bottom),
+        Transform::Flipped270 => (bottom, right),
+        // Transforms from a newer wl_output are taken to be none
+        _ => (x, y),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem;
+
+    const UPRIGHT_CORNERS: [(u32, u32); 4] = [(0, 0), (2, 0), (0, 1), (2, 1)];
+
+    /// Size of the buffer holding a 3x2 upright image with each transform, and where its top-left,
+    /// top-right, bottom-left and bottom-right corners end up in it. The buffer holds the image
+    /// rotated counter-clockwise, after flipping it for the flipped transforms.
+    const TRANSFORMS: &'static [(Transform, (u32, u32), [(u32, u32); 4])] = &[
+        (Transform::Normal, (3, 2), [(0, 0), (2, 0), (0, 1), (2, 1)]),
+        (Transform::_90, (2, 3), [(0, 2), (0, 0), (1, 2), (1, 0)]),
+        (Transform::_180, (3, 2), [(2, 1), (0, 1), (2, 0), (0, 0)]),
+        (Transform::_270, (2, 3), [(1, 0), (1, 2), (0, 0), (0, 2)]),
+        (Transform::Flipped, (3, 2), [(2, 0), (0, 0), (2, 1), (0, 1)]),
+        (Transform::Flipped90, (2, 3), [(0, 0), (0, 2), (1, 0), (1, 2)]),
+        (Transform::Flipped180, (3, 2), [(0, 1), (2, 1), (0, 0), (2, 0)]),
+        (Transform::Flipped270, (2, 3), [(1, 2), (1, 0), (0, 2), (0, 0)]),
+    ];
+
+    #[test]
+    fn upright_size_of_every_transform() {
+        for &(transform, (width, height), _) in TRANSFORMS.iter() {
+            assert_eq!(upright_size(transform, width, height), (3, 2), "{:?}", transform);
+        }
+    }
+
+    #[test]
+    fn buffer_position_of_every_corner() {
+        for &(transform, _, corners) in TRANSFORMS.iter() {
+            for (&(x, y), &expected) in UPRIGHT_CORNERS.iter().zip(corners.iter()) {
+                assert_eq!(buffer_position(transform, x, y, 3, 2), expected, "({}, {}) with {:?}", x, y, transform);
+            }
+        }
+    }
+
+    #[test]
+    fn blit_makes_transformed_buffers_upright() {
+        for &(transform, (width, height), corners) in TRANSFORMS.iter() {
+            // Every corner gets its own blue value
+            let mut buffer = vec![0u8; (width * height * 4) as usize];
+            for (i, &(x, y)) in corners.iter().enumerate() {
+                buffer[((y * width + x) * 4) as usize] = 10 * (i as u8 + 1);
+            }
+            let mut frame: obs_sys::obs_source_frame = unsafe { mem::zeroed() };
+            frame.data[0] = buffer.as_mut_ptr();
+            frame.linesize[0] = width * 4;
+            frame.width = width;
+            frame.height = height;
+            frame.format = obs_sys::video_format::VIDEO_FORMAT_BGRX;
+
+            let mut canvas = Canvas::new(3, 2);
+            assert_eq!(canvas.blit(&frame, &Region::new(0, 0, 3, 2), transform), Some(Region::new(0, 0, 3, 2)));
+            for (i, &(x, y)) in UPRIGHT_CORNERS.iter().enumerate() {
+                assert_eq!(canvas.as_slice()[((y * 3 + x) * 4) as usize], 10 * (i as u8 + 1), "({}, {}) with {:?}", x, y, transform);
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use ::obs::sys as obs_sys;
+use wayland_client::protocol::wl_shm::Format;
+
+/// Describes how a wl_shm buffer has to be treated before OBS can display it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShmFormat {
+    pub video_format: obs_sys::video_format,
+    pub conversion: Conversion,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conversion {
+    /// Buffer can be handed to OBS as-is
+    None,
+    /// Byte order matches `video_format`, but the padding byte has to be made opaque
+    FillAlpha,
+    /// Byte indices of the blue, green and red channels (and alpha, if present) within each
+    /// source pixel, producing a BGRA pixel
+    Swizzle([usize; 3], Option<usize>),
+    /// Bit offsets of the red and blue channels within a little-endian 2:10:10:10 pixel, and
+    /// whether the top two bits carry alpha. Channels are truncated to 8 bits, producing a BGRA
+    /// pixel.
+    Unpack2101010 {
+        red_shift: u32,
+        blue_shift: u32,
+        alpha: bool,
+    },
+}
+
+impl ShmFormat {
+    fn new(video_format: obs_sys::video_format, conversion: Conversion) -> ShmFormat {
+        ShmFormat {
+            video_format: video_format,
+            conversion: conversion,
+        }
+    }
+
+    /// Maps a wl_shm format (little-endian, named from the most significant byte) to the format
+    /// OBS will see, or `None` if the format is not supported
+    pub fn from_wl_shm(format: Format) -> Option<ShmFormat> {
+        use obs_sys::video_format::*;
+        let ret = match format {
+            // bytes: B G R A
+            Format::Argb8888 => ShmFormat::new(VIDEO_FORMAT_BGRA, Conversion::None),
+            // bytes: B G R X
+            Format::Xrgb8888 => ShmFormat::new(VIDEO_FORMAT_BGRX, Conversion::None),
+            // bytes: R G B A
+            Format::Abgr8888 => ShmFormat::new(VIDEO_FORMAT_RGBA, Conversion::None),
+            // bytes: R G B X, OBS has no RGBX so it is uploaded as opaque RGBA
+            Format::Xbgr8888 => ShmFormat::new(VIDEO_FORMAT_RGBA, Conversion::FillAlpha),
+            // bytes: A B G R
+            Format::Rgba8888 => ShmFormat::new(VIDEO_FORMAT_BGRA, Conversion::Swizzle([1, 2, 3], Some(0))),
+            // bytes: X B G R
+            Format::Rgbx8888 => ShmFormat::new(VIDEO_FORMAT_BGRX, Conversion::Swizzle([1, 2, 3], None)),
+            // bytes: A R G B
+            Format::Bgra8888 => ShmFormat::new(VIDEO_FORMAT_BGRA, Conversion::Swizzle([3, 2, 1], Some(0))),
+            // bytes: X R G B
+            Format::Bgrx8888 => ShmFormat::new(VIDEO_FORMAT_BGRX, Conversion::Swizzle([3, 2, 1], None)),
+            // 32-bit words: X:R:G:B / A:R:G:B
+            Format::Xrgb2101010 => ShmFormat::new(VIDEO_FORMAT_BGRX, Conversion::Unpack2101010 { red_shift: 20, blue_shift: 0, alpha: false }),
+            Format::Argb2101010 => ShmFormat::new(VIDEO_FORMAT_BGRA, Conversion::Unpack2101010 { red_shift: 20, blue_shift: 0, alpha: true }),
+            // 32-bit words: X:B:G:R / A:B:G:R
+            Format::Xbgr2101010 => ShmFormat::new(VIDEO_FORMAT_BGRX, Conversion::Unpack2101010 { red_shift: 0, blue_shift: 20, alpha: false }),
+            Format::Abgr2101010 => ShmFormat::new(VIDEO_FORMAT_BGRA, Conversion::Unpack2101010 { red_shift: 0, blue_shift: 20, alpha: true }),
+            _ => return None,
+        };
+        Some(ret)
+    }
+
+    #[inline(always)]
+    pub fn needs_conversion(&self) -> bool {
+        self.conversion != Conversion::None
+    }
+
+    /// Converts `src` (`height` rows of `stride` bytes) into a buffer of the same layout that
+    /// can be handed to OBS as `video_format`
+    pub fn convert(&self, src: &[u8], width: usize, height: usize, stride: usize) -> Vec<u8> {
+        let mut dst = vec![0u8; stride * height];
+        for (src_row, dst_row) in src.chunks(stride).zip(dst.chunks_mut(stride)).take(height) {
+            let src_px = src_row[..(width * 4)].chunks(4);
+            let dst_px = dst_row[..(width * 4)].chunks_mut(4);
+            match self.conversion {
+                Conversion::None => {
+                    for (s, d) in src_px.zip(dst_px) {
+                        d.copy_from_slice(s);
+                    }
+                },
+                Conversion::FillAlpha => {
+                    for (s, d) in src_px.zip(dst_px) {
+                        d[..3].copy_from_slice(&s[..3]);
+                        d[3] = 0xff;
+                    }
+                },
+                Conversion::Swizzle([b, g, r], a) => {
+                    for (s, d) in src_px.zip(dst_px) {
+                        d[0] = s[b];
+                        d[1] = s[g];
+                        d[2] = s[r];
+                        d[3] = a.map(|a| s[a]).unwrap_or(0xff);
+                    }
+                },
+                Conversion::Unpack2101010 { red_shift, blue_shift, alpha } => {
+                    for (s, d) in src_px.zip(dst_px) {
+                        let px = u32::from_le_bytes([s[0], s[1], s[2], s[3]]);
+                        let channel = |shift: u32| (px >> (shift + 2)) as u8;
+                        d[0] = channel(blue_shift);
+                        d[1] = channel(10);
+                        d[2] = channel(red_shift);
+                        d[3] = if alpha {
+                            ((px >> 30) as u8) * 0x55
+                        } else {
+                            0xff
+                        };
+                    }
+                },
+            }
+        }
+        dst
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use obs_sys::video_format::*;
+
+    /// Distinct value of every channel, so swapped channels show
+    fn channel_value(channel: char) -> u8 {
+        match channel {
+            'R' => 0x11,
+            'G' => 0x22,
+            'B' => 0x33,
+            'A' => 0x44,
+            'X' => 0x55,
+            _ => unreachable!(),
+        }
+    }
+
+    /// One pixel of an 8-bit format named like wl_shm, from the most significant byte of a
+    /// little-endian word
+    fn pixel(channels: &str) -> Vec<u8> {
+        channels.chars().rev().map(channel_value).collect()
+    }
+
+    /// Converts a pixel of `format`, laid out as `channels`, and checks it reads right as the
+    /// video format OBS is told about
+    fn check_8888(format: Format, channels: &str) {
+        let shm_format = ShmFormat::from_wl_shm(format).unwrap();
+        let dst = shm_format.convert(&pixel(channels), 1, 1, 4);
+        let obs_channels = match shm_format.video_format {
+            VIDEO_FORMAT_BGRA => "BGRA",
+            VIDEO_FORMAT_BGRX => "BGRX",
+            VIDEO_FORMAT_RGBA => "RGBA",
+            f => panic!("unexpected video format {:?} for {:?}", f, format),
+        };
+        for (c, &value) in obs_channels.chars().zip(dst.iter()) {
+            let expected = match c {
+                // Padding is never read
+                'X' => continue,
+                'A' if !channels.contains('A') => 0xff,
+                c => channel_value(c),
+            };
+            assert_eq!(value, expected, "channel {} of {:?}", c, format);
+        }
+    }
+
+    #[test]
+    fn formats_that_need_no_conversion() {
+        for &format in [Format::Argb8888, Format::Xrgb8888, Format::Abgr8888].iter() {
+            assert_eq!(ShmFormat::from_wl_shm(format).map(|f| f.conversion), Some(Conversion::None));
+        }
+        check_8888(Format::Argb8888, "ARGB");
+        check_8888(Format::Xrgb8888, "XRGB");
+        check_8888(Format::Abgr8888, "ABGR");
+    }
+
+    #[test]
+    fn padding_is_made_opaque() {
+        assert_eq!(ShmFormat::from_wl_shm(Format::Xbgr8888).map(|f| f.conversion), Some(Conversion::FillAlpha));
+        check_8888(Format::Xbgr8888, "XBGR");
+    }
+
+    #[test]
+    fn swizzled_formats() {
+        check_8888(Format::Rgba8888, "RGBA");
+        check_8888(Format::Rgbx8888, "RGBX");
+        check_8888(Format::Bgra8888, "BGRA");
+        check_8888(Format::Bgrx8888, "BGRX");
+    }
+
+    #[test]
+    fn unsupported_formats() {
+        assert_eq!(ShmFormat::from_wl_shm(Format::Rgb565), None);
+        assert_eq!(ShmFormat::from_wl_shm(Format::Nv12), None);
+    }
+
+    #[test]
+    fn convert_keeps_the_stride() {
+        let shm_format = ShmFormat::from_wl_shm(Format::Xbgr8888).unwrap();
+        let mut src = pixel("XBGR");
+        src.extend_from_slice(&[0xee; 4]);
+        let dst = shm_format.convert(&src, 1, 1, 8);
+        assert_eq!(dst, vec![0x11, 0x22, 0x33, 0xff, 0, 0, 0, 0]);
+    }
+
+    /// One pixel of a 2:10:10:10 format named like wl_shm, with `value` in `channel` and every other
+    /// channel zero
+    fn pixel_2101010(channels: &str, channel: char, value: u32) -> Vec<u8> {
+        let shift = match channels.find(channel).unwrap() {
+            0 => 30,
+            1 => 20,
+            2 => 10,
+            _ => 0,
+        };
+        (value << shift).to_le_bytes().to_vec()
+    }
+
+    /// Checks every channel of `format` lands in the right byte of the BGRA/BGRX pixel on its own,
+    /// truncated to its top 8 bits
+    fn check_2101010(format: Format, channels: &str) {
+        let shm_format = ShmFormat::from_wl_shm(format).unwrap();
+        let has_alpha = channels.starts_with('A');
+        let expected_format = if has_alpha { VIDEO_FORMAT_BGRA } else { VIDEO_FORMAT_BGRX };
+        assert_eq!(shm_format.video_format, expected_format);
+        for &(channel, index) in [('B', 0), ('G', 1), ('R', 2)].iter() {
+            for &(value, expected) in [(0x2b5, 0xad), (0x3ff, 0xff), (0x003, 0x00)].iter() {
+                let dst = shm_format.convert(&pixel_2101010(channels, channel, value), 1, 1, 4);
+                for i in 0..3 {
+                    let want = if i == index { expected } else { 0 };
+                    assert_eq!(dst[i], want, "byte {} for {}={:#x} in {:?}", i, channel, value, format);
+                }
+            }
+        }
+        let top = channels.chars().next().unwrap();
+        for &(value, expected) in [(0b00, 0x00), (0b01, 0x55), (0b10, 0xaa), (0b11, 0xff)].iter() {
+            let dst = shm_format.convert(&pixel_2101010(channels, top, value), 1, 1, 4);
+            // Padding bits never show through
+            let want = if has_alpha { expected } else { 0xff };
+            assert_eq!(dst[3], want, "alpha for {}={:#b} in {:?}", top, value, format);
+        }
+    }
+
+    #[test]
+    fn unpacks_2101010_formats() {
+        check_2101010(Format::Xrgb2101010, "XRGB");
+        check_2101010(Format::Argb2101010, "ARGB");
+        check_2101010(Format::Xbgr2101010, "XBGR");
+        check_2101010(Format::Abgr2101010, "ABGR");
+    }
+}
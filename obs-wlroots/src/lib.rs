@@ -1,22 +1,36 @@
+#[macro_use]
+extern crate bitflags;
 extern crate libc;
 extern crate obs;
+extern crate regex;
 extern crate wayland_client;
+extern crate wayland_commons;
 
 pub use obs::sys as obs_sys;
 
 use obs::obs_declare_module;
 
 static mut SOURCE_INFO: Option<obs::source::SourceInfo> = None;
+static mut ASYNC_SOURCE_INFO: Option<obs::source::SourceInfo> = None;
 
 pub mod source;
 pub mod shm;
+pub mod region;
+pub mod format;
+pub mod toplevel;
+pub mod output;
+pub mod composite;
 pub(crate) mod mmap;
+pub(crate) mod mailbox;
+pub(crate) mod protocols;
 
 #[no_mangle]
 pub extern "C" fn obs_module_load() -> bool {
     unsafe {
         SOURCE_INFO = Some(obs::source::video_source_info::<source::WlrSource>());
         obs::source::register_source(SOURCE_INFO.as_ref().unwrap().as_raw());
+        ASYNC_SOURCE_INFO = Some(obs::source::async_video_source_info::<source::WlrAsyncSource>());
+        obs::source::register_source(ASYNC_SOURCE_INFO.as_ref().unwrap().as_raw());
     }
     println!("libobs_wlroots loaded");
     true
@@ -0,0 +1,73 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Single-slot hand-off between threads that only ever holds the newest value
+pub struct Mailbox<T>(Arc<Mutex<Option<T>>>);
+
+impl<T> Mailbox<T> {
+    pub fn new() -> Mailbox<T> {
+        Mailbox(Arc::new(Mutex::new(None)))
+    }
+
+    /// Replaces the current value, returning the previous one if it was never taken
+    pub fn post(&self, value: T) -> Option<T> {
+        self.lock().replace(value)
+    }
+
+    /// Like `post`, but a previous value that was never taken is first folded into `value`
+    pub fn post_with<F: FnOnce(T, &mut T)>(&self, mut value: T, merge: F) {
+        let mut slot = self.lock();
+        if let Some(previous) = slot.take() {
+            merge(previous, &mut value);
+        }
+        *slot = Some(value);
+    }
+
+    /// Takes the current value without waiting for a new one
+    pub fn take(&self) -> Option<T> {
+        self.lock().take()
+    }
+
+    // A panicking producer must not take the consumer down with it
+    fn lock(&self) -> MutexGuard<Option<T>> {
+        self.0.lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T> Clone for Mailbox<T> {
+    fn clone(&self) -> Self {
+        Mailbox(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_only_the_newest_value() {
+        let mailbox = Mailbox::new();
+        assert_eq!(mailbox.post(1), None);
+        assert_eq!(mailbox.post(2), Some(1));
+        assert_eq!(mailbox.clone().take(), Some(2));
+        assert_eq!(mailbox.take(), None);
+    }
+
+    fn prepend(mut previous: Vec<u32>, next: &mut Vec<u32>) {
+        previous.append(next);
+        *next = previous;
+    }
+
+    #[test]
+    fn merges_values_that_were_never_taken() {
+        let mailbox = Mailbox::new();
+        mailbox.post_with(vec![1], prepend);
+        mailbox.post_with(vec![2], prepend);
+        mailbox.post_with(vec![3, 4], prepend);
+        assert_eq!(mailbox.take(), Some(vec![1, 2, 3, 4]));
+
+        // Nothing left to merge with after a take
+        mailbox.post_with(vec![5], |_, _| panic!("merged with a taken value"));
+        assert_eq!(mailbox.take(), Some(vec![5]));
+    }
+}
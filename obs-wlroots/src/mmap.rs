@@ -24,8 +24,27 @@ impl MappedMemory {
     pub fn as_raw(&self) -> *mut libc::c_void {
         self.addr
     }
+
+    #[inline(always)]
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(self.addr as *const u8, self.size)
+        }
+    }
+
+    /// Writable view of a mapping created with `PROT_WRITE`. The caller has to make sure nobody
+    /// reads the mapping meanwhile, the same way the compositor only writes into buffers that aren't
+    /// leased out.
+    #[inline(always)]
+    pub unsafe fn as_mut_slice(&self) -> &mut [u8] {
+        std::slice::from_raw_parts_mut(self.addr as *mut u8, self.size)
+    }
 }
 
+// The mapping is only read through shared references, except while it isn't shared
+unsafe impl Send for MappedMemory {}
+unsafe impl Sync for MappedMemory {}
+
 impl Drop for MappedMemory {
     fn drop(&mut self) {
         unsafe {
@@ -0,0 +1,268 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// What the compositor tells us about an output, gathered from wl_output and xdg_output
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OutputInfo {
+    /// Connector name, e.g. `DP-3`
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    /// Physical size in millimeters
+    pub physical_size: Option<(i32, i32)>,
+}
+
+impl OutputInfo {
+    /// Name of the monitor itself, regardless of the connector it's plugged into. Compositors
+    /// usually describe outputs as "make model serial (connector)", so the connector is dropped
+    /// from the description. Without a description, make, model and size have to do.
+    pub fn monitor(&self) -> Option<String> {
+        if let Some(description) = self.description.as_ref() {
+            let mut monitor: &str = description;
+            if let Some(name) = self.name.as_ref() {
+                let suffix = format!("({})", name);
+                if monitor.ends_with(&suffix) {
+                    monitor = &monitor[..(monitor.len() - suffix.len())];
+                }
+            }
+            let monitor = monitor.trim();
+            if monitor.len() != 0 {
+                return Some(monitor.into());
+            }
+        }
+        let mut parts: Vec<String> = self.make.iter()
+            .chain(self.model.iter())
+            .filter(|s| s.len() != 0 && s.as_str() != "Unknown")
+            .cloned()
+            .collect();
+        if parts.is_empty() {
+            return None;
+        }
+        if let Some((width, height)) = self.physical_size.filter(|&(w, h)| w > 0 && h > 0) {
+            parts.push(format!("{}x{}mm", width, height));
+        }
+        Some(parts.join(" "))
+    }
+
+    /// Identity to store in the source settings, once the connector name is known
+    pub fn id(&self) -> Option<OutputId> {
+        self.name.as_ref().map(|name| OutputId {
+            name: name.clone(),
+            monitor: self.monitor(),
+        })
+    }
+
+    /// Name to show in the properties
+    pub fn label(&self) -> String {
+        let name = self.name.as_ref()
+            .map(|s| s.as_str())
+            .unwrap_or("<unknown>");
+        match self.monitor() {
+            Some(monitor) => format!("{} ({})", name, monitor),
+            None => name.into(),
+        }
+    }
+}
+
+/// An output as selected by the user: its connector name and, if known, the monitor on it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OutputId {
+    pub name: String,
+    pub monitor: Option<String>,
+}
+
+/// Formats as `"name:monitor"`, or just `"name"` if the monitor is unknown
+impl fmt::Display for OutputId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.monitor.as_ref() {
+            Some(monitor) => write!(f, "{}:{}", self.name, monitor),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Parses `"name:monitor"`. Connector names don't contain colons, and settings from before monitors
+/// were tracked are just the name.
+impl FromStr for OutputId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputId, String> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        if name.len() == 0 {
+            return Err(format!("Invalid output \"{}\", expected \"name:monitor\"", s));
+        }
+        Ok(OutputId {
+            name: name.into(),
+            monitor: parts.next()
+                .filter(|s| s.len() != 0)
+                .map(|s| s.into()),
+        })
+    }
+}
+
+/// How the `output` setting is matched against the outputs that are present
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputMatch {
+    /// The same monitor, on whichever connector it's plugged into, falling back to the connector
+    /// name for monitors that can't be told apart
+    Monitor,
+    /// Whatever is plugged into the same connector
+    Connector,
+}
+
+impl OutputMatch {
+    pub const ALL: &'static [OutputMatch] = &[
+        OutputMatch::Monitor,
+        OutputMatch::Connector,
+    ];
+
+    /// Value stored in the source settings
+    pub fn id(&self) -> &'static str {
+        match self {
+            &OutputMatch::Monitor => "monitor",
+            &OutputMatch::Connector => "connector",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            &OutputMatch::Monitor => "Same monitor (make, model and serial)",
+            &OutputMatch::Connector => "Same connector",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<OutputMatch> {
+        OutputMatch::ALL.iter()
+            .cloned()
+            .find(|m| m.id() == id)
+    }
+
+    /// Finds `id` among `outputs`, returning its index. A monitor on its old connector beats the
+    /// same model elsewhere, which beats whatever is on the old connector.
+    pub fn find(&self, id: &OutputId, outputs: &[OutputId]) -> Option<usize> {
+        let by_name = || outputs.iter().position(|output| output.name == id.name);
+        match (self, id.monitor.as_ref()) {
+            (&OutputMatch::Monitor, Some(monitor)) => {
+                outputs.iter().position(|output| output == id)
+                    .or_else(|| outputs.iter().position(|output| output.monitor.as_ref() == Some(monitor)))
+                    .or_else(|| {
+                        // Don't grab another monitor that happens to be on the old connector
+                        by_name().filter(|&i| outputs[i].monitor.is_none())
+                    })
+            },
+            _ => by_name(),
+        }
+    }
+}
+
+impl Default for OutputMatch {
+    fn default() -> Self {
+        OutputMatch::Monitor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, description: Option<&str>) -> OutputInfo {
+        OutputInfo {
+            name: Some(name.into()),
+            description: description.map(|s| s.into()),
+            make: Some("Dell Inc.".into()),
+            model: Some("DELL U2720Q".into()),
+            physical_size: Some((600, 340)),
+        }
+    }
+
+    fn id(s: &str) -> OutputId {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn monitor_drops_the_connector_from_the_description() {
+        let output = info("DP-1", Some("Dell Inc. DELL U2720Q 8ABC123 (DP-1)"));
+        assert_eq!(output.monitor(), Some("Dell Inc. DELL U2720Q 8ABC123".into()));
+        assert_eq!(output.id(), Some(id("DP-1:Dell Inc. DELL U2720Q 8ABC123")));
+        assert_eq!(output.label(), "DP-1 (Dell Inc. DELL U2720Q 8ABC123)");
+        // Only a matching connector is dropped
+        let output = info("DP-2", Some("Dell Inc. DELL U2720Q 8ABC123 (DP-1)"));
+        assert_eq!(output.monitor(), Some("Dell Inc. DELL U2720Q 8ABC123 (DP-1)".into()));
+    }
+
+    #[test]
+    fn monitor_without_description_falls_back_to_make_and_model() {
+        assert_eq!(info("DP-1", None).monitor(), Some("Dell Inc. DELL U2720Q 600x340mm".into()));
+        // A description that is just the connector says nothing about the monitor
+        assert_eq!(info("DP-1", Some("(DP-1)")).monitor(), Some("Dell Inc. DELL U2720Q 600x340mm".into()));
+
+        let output = OutputInfo {
+            name: Some("HDMI-A-1".into()),
+            make: Some("Unknown".into()),
+            model: Some("".into()),
+            physical_size: Some((0, 0)),
+            ..OutputInfo::default()
+        };
+        assert_eq!(output.monitor(), None);
+        assert_eq!(output.id(), Some(id("HDMI-A-1")));
+        assert_eq!(output.label(), "HDMI-A-1");
+        assert_eq!(OutputInfo::default().id(), None);
+    }
+
+    #[test]
+    fn parses_name_and_monitor() {
+        assert_eq!(id("DP-1:Dell Inc. DELL U2720Q 8ABC123"), OutputId {
+            name: "DP-1".into(),
+            monitor: Some("Dell Inc. DELL U2720Q 8ABC123".into()),
+        });
+        // Monitors may contain colons
+        assert_eq!(id("DP-1:Foo: Bar").monitor, Some("Foo: Bar".into()));
+        // Settings from before monitors were tracked
+        assert_eq!(id("DP-1"), OutputId {
+            name: "DP-1".into(),
+            monitor: None,
+        });
+        assert_eq!(id("DP-1:").monitor, None);
+        assert!("".parse::<OutputId>().is_err());
+        assert!(":Dell".parse::<OutputId>().is_err());
+
+        for s in ["DP-1:Dell Inc. DELL U2720Q 8ABC123", "DP-1:Foo: Bar", "DP-1"].iter() {
+            assert_eq!(id(s).to_string(), *s);
+        }
+    }
+
+    #[test]
+    fn finds_a_monitor_on_another_connector() {
+        let wanted = id("DP-1:Dell U2720Q 8ABC123");
+        let outputs = [id("DP-1:LG 27GL850"), id("DP-2:Dell U2720Q 8ABC123")];
+        assert_eq!(OutputMatch::Monitor.find(&wanted, &outputs), Some(1));
+        assert_eq!(OutputMatch::Connector.find(&wanted, &outputs), Some(0));
+        // Gone altogether, and another monitor is on the old connector
+        assert_eq!(OutputMatch::Monitor.find(&wanted, &outputs[..1]), None);
+        assert_eq!(OutputMatch::Connector.find(&wanted, &outputs[..1]), Some(0));
+    }
+
+    #[test]
+    fn identical_monitors_are_told_apart_by_connector() {
+        // No serial, so both describe themselves the same
+        let wanted = id("DP-2:Dell U2720Q");
+        let outputs = [id("DP-1:Dell U2720Q"), id("DP-2:Dell U2720Q")];
+        assert_eq!(OutputMatch::Monitor.find(&wanted, &outputs), Some(1));
+        assert_eq!(OutputMatch::Monitor.find(&id("DP-1:Dell U2720Q"), &outputs), Some(0));
+        // Both moved, the first one found wins
+        let moved = [id("DP-3:Dell U2720Q"), id("DP-4:Dell U2720Q")];
+        assert_eq!(OutputMatch::Monitor.find(&wanted, &moved), Some(0));
+    }
+
+    #[test]
+    fn falls_back_to_the_connector_without_a_monitor() {
+        // The output on the old connector doesn't tell its monitor
+        let outputs = [id("DP-1:LG 27GL850"), id("HDMI-A-1")];
+        assert_eq!(OutputMatch::Monitor.find(&id("HDMI-A-1:Dell U2720Q"), &outputs), Some(1));
+        // The setting doesn't name a monitor
+        assert_eq!(OutputMatch::Monitor.find(&id("DP-1"), &outputs), Some(0));
+        assert_eq!(OutputMatch::Monitor.find(&id("DP-2"), &outputs), None);
+    }
+}
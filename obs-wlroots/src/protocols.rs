@@ -0,0 +1,58 @@
+//! Client bindings for the protocols in `protocols/`, generated by `build.rs`
+
+#![allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+#![allow(non_upper_case_globals, non_snake_case, unused_imports)]
+
+pub mod ext_foreign_toplevel_list {
+    pub mod client {
+        pub(crate) use wayland_client::{Main, Attached, Proxy, ProxyMap, AnonymousObject};
+        pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+        pub(crate) use wayland_commons::{Interface, MessageGroup};
+        pub(crate) use wayland_commons::wire::{Argument, MessageDesc, ArgumentType, Message};
+        pub(crate) use wayland_commons::smallvec;
+        pub(crate) use wayland_client::sys;
+        include!(concat!(env!("OUT_DIR"), "/ext_foreign_toplevel_list_v1_client_api.rs"));
+    }
+}
+
+pub mod ext_image_capture_source {
+    pub mod client {
+        pub(crate) use wayland_client::{Main, Attached, Proxy, ProxyMap, AnonymousObject};
+        pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+        pub(crate) use wayland_commons::{Interface, MessageGroup};
+        pub(crate) use wayland_commons::wire::{Argument, MessageDesc, ArgumentType, Message};
+        pub(crate) use wayland_commons::smallvec;
+        pub(crate) use wayland_client::sys;
+        pub(crate) use wayland_client::protocol::wl_output;
+        pub(crate) use super::super::ext_foreign_toplevel_list::client::ext_foreign_toplevel_handle_v1;
+        include!(concat!(env!("OUT_DIR"), "/ext_image_capture_source_v1_client_api.rs"));
+    }
+}
+
+pub mod ext_image_copy_capture {
+    pub mod client {
+        pub(crate) use wayland_client::{Main, Attached, Proxy, ProxyMap, AnonymousObject};
+        pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+        pub(crate) use wayland_commons::{Interface, MessageGroup};
+        pub(crate) use wayland_commons::wire::{Argument, MessageDesc, ArgumentType, Message};
+        pub(crate) use wayland_commons::smallvec;
+        pub(crate) use wayland_client::sys;
+        pub(crate) use wayland_client::protocol::{wl_buffer, wl_output, wl_pointer, wl_shm};
+        pub(crate) use super::super::ext_image_capture_source::client::ext_image_capture_source_v1;
+        include!(concat!(env!("OUT_DIR"), "/ext_image_copy_capture_v1_client_api.rs"));
+    }
+}
+
+/// wlr-screencopy up to v3, `wayland-protocols` 0.24 only has v1
+pub mod wlr_screencopy {
+    pub mod client {
+        pub(crate) use wayland_client::{Main, Attached, Proxy, ProxyMap, AnonymousObject};
+        pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+        pub(crate) use wayland_commons::{Interface, MessageGroup};
+        pub(crate) use wayland_commons::wire::{Argument, MessageDesc, ArgumentType, Message};
+        pub(crate) use wayland_commons::smallvec;
+        pub(crate) use wayland_client::sys;
+        pub(crate) use wayland_client::protocol::{wl_buffer, wl_output};
+        include!(concat!(env!("OUT_DIR"), "/wlr_screencopy_unstable_v1_client_api.rs"));
+    }
+}
@@ -0,0 +1,203 @@
+use std::cmp;
+use std::fmt;
+use std::str::FromStr;
+
+/// An axis-aligned rectangle, in output-logical or buffer coordinates depending on where it came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Region {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Region {
+        Region {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    pub fn translate(&self, dx: i32, dy: i32) -> Region {
+        Region::new(self.x + dx, self.y + dy, self.width, self.height)
+    }
+
+    pub fn intersect(&self, other: &Region) -> Option<Region> {
+        let x = cmp::max(self.x, other.x);
+        let y = cmp::max(self.y, other.y);
+        let right = cmp::min(self.x + self.width, other.x + other.width);
+        let bottom = cmp::min(self.y + self.height, other.y + other.height);
+        Some(Region::new(x, y, right - x, bottom - y))
+            .filter(|r| !r.is_empty())
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{} {}x{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// Parses a slurp-style `"x,y wxh"` geometry string
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Region, String> {
+        fn parse_pair(s: &str, sep: char) -> Option<(i32, i32)> {
+            let mut it = s.splitn(2, sep);
+            let a = it.next()?.trim().parse().ok()?;
+            let b = it.next()?.trim().parse().ok()?;
+            Some((a, b))
+        }
+
+        let s = s.trim();
+        let mut parts = s.split_whitespace();
+        let position = parts.next().and_then(|p| parse_pair(p, ','));
+        let size = parts.next().and_then(|p| parse_pair(p, 'x'));
+        match (position, size, parts.next()) {
+            (Some((x, y)), Some((width, height)), None) => {
+                let region = Region::new(x, y, width, height);
+                if region.is_empty() {
+                    Err(format!("Empty region: \"{}\"", s))
+                } else {
+                    Ok(region)
+                }
+            },
+            _ => Err(format!("Invalid region \"{}\", expected \"x,y wxh\"", s)),
+        }
+    }
+}
+
+/// Total area of `regions` that falls within `bounds`. Overlaps are counted more than once, which
+/// errs on the side of more damage.
+pub fn covered_area(regions: &[Region], bounds: &Region) -> i64 {
+    regions.iter()
+        .filter_map(|r| r.intersect(bounds))
+        .map(|r| (r.width as i64) * (r.height as i64))
+        .sum()
+}
+
+/// The parts of `damage` within `bounds`, or `None` if they cover at least half of it, in which case
+/// copying all of `bounds` at once is cheaper than going piece by piece
+pub fn clip_damage(damage: &[Region], bounds: &Region) -> Option<Vec<Region>> {
+    if covered_area(damage, bounds) * 2 >= (bounds.width as i64) * (bounds.height as i64) {
+        return None;
+    }
+    Some(damage.iter().filter_map(|r| r.intersect(bounds)).collect())
+}
+
+/// Region of an output to capture, as configured by the user
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionSpec {
+    /// Coordinates relative to the output's top-left corner
+    Output(Region),
+    /// Coordinates in the compositor's global layout, e.g. from `slurp`
+    Layout(Region),
+}
+
+impl RegionSpec {
+    /// Resolves this spec against the output's logical geometry (in layout coordinates),
+    /// returning the output-local region to capture, or `None` if it lies outside the output
+    pub fn resolve(&self, geometry: &Region) -> Option<Region> {
+        let local = match self {
+            &RegionSpec::Output(r) => r,
+            &RegionSpec::Layout(r) => r.translate(-geometry.x, -geometry.y),
+        };
+        local.intersect(&Region::new(0, 0, geometry.width, geometry.height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_slurp_geometry() {
+        assert_eq!("10,20 300x400".parse::<Region>(), Ok(Region::new(10, 20, 300, 400)));
+        assert_eq!("  -1920,0 1920x1080\n".parse::<Region>(), Ok(Region::new(-1920, 0, 1920, 1080)));
+    }
+
+    #[test]
+    fn rejects_malformed_geometry() {
+        for s in ["", "10,20", "10,20 300", "10 20 300x400", "10,20 300x400 5", "a,b cxd", "10,20 0x400", "10,20 300x-4"].iter() {
+            assert!(s.parse::<Region>().is_err(), "{:?} parsed", s);
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let region = Region::new(-5, 7, 640, 480);
+        assert_eq!(region.to_string().parse::<Region>(), Ok(region));
+    }
+
+    #[test]
+    fn intersects_overlapping_regions() {
+        let a = Region::new(0, 0, 100, 100);
+        let b = Region::new(50, 60, 100, 100);
+        assert_eq!(a.intersect(&b), Some(Region::new(50, 60, 50, 40)));
+        assert_eq!(b.intersect(&a), Some(Region::new(50, 60, 50, 40)));
+        assert_eq!(a.intersect(&Region::new(10, 10, 5, 5)), Some(Region::new(10, 10, 5, 5)));
+    }
+
+    #[test]
+    fn disjoint_or_touching_regions_dont_intersect() {
+        let a = Region::new(0, 0, 100, 100);
+        assert_eq!(a.intersect(&Region::new(100, 0, 10, 10)), None);
+        assert_eq!(a.intersect(&Region::new(0, 200, 10, 10)), None);
+    }
+
+    #[test]
+    fn clips_damage_to_bounds() {
+        let bounds = Region::new(0, 0, 100, 100);
+        let damage = [Region::new(-10, -10, 20, 20), Region::new(90, 50, 20, 5), Region::new(200, 0, 10, 10)];
+        assert_eq!(clip_damage(&damage, &bounds), Some(vec![Region::new(0, 0, 10, 10), Region::new(90, 50, 10, 5)]));
+        assert_eq!(clip_damage(&[], &bounds), Some(vec![]));
+    }
+
+    #[test]
+    fn large_damage_is_not_clipped() {
+        let bounds = Region::new(0, 0, 100, 100);
+        assert_eq!(clip_damage(&[Region::new(0, 0, 100, 50)], &bounds), None);
+        assert_eq!(clip_damage(&[Region::new(0, 0, 100, 49)], &bounds), Some(vec![Region::new(0, 0, 100, 49)]));
+        // Overlaps count twice, erring on the side of a full upload
+        assert_eq!(clip_damage(&[Region::new(0, 0, 100, 25), Region::new(0, 0, 100, 25)], &bounds), None);
+        // Only the part within bounds counts
+        assert_eq!(clip_damage(&[Region::new(0, 90, 100, 100)], &bounds), Some(vec![Region::new(0, 90, 100, 10)]));
+    }
+
+    #[test]
+    fn resolves_output_region() {
+        let geometry = Region::new(1920, 0, 2560, 1440);
+        let spec = RegionSpec::Output(Region::new(100, 100, 800, 600));
+        assert_eq!(spec.resolve(&geometry), Some(Region::new(100, 100, 800, 600)));
+    }
+
+    #[test]
+    fn resolves_layout_region_relative_to_output() {
+        let geometry = Region::new(1920, 0, 2560, 1440);
+        let spec = RegionSpec::Layout(Region::new(2020, 100, 800, 600));
+        assert_eq!(spec.resolve(&geometry), Some(Region::new(100, 100, 800, 600)));
+    }
+
+    #[test]
+    fn clips_region_to_output() {
+        let geometry = Region::new(0, 0, 1920, 1080);
+        let spec = RegionSpec::Layout(Region::new(1800, 1000, 400, 400));
+        assert_eq!(spec.resolve(&geometry), Some(Region::new(1800, 1000, 120, 80)));
+    }
+
+    #[test]
+    fn region_outside_output_resolves_to_none() {
+        let geometry = Region::new(1920, 0, 2560, 1440);
+        assert_eq!(RegionSpec::Layout(Region::new(0, 0, 800, 600)).resolve(&geometry), None);
+        assert_eq!(RegionSpec::Output(Region::new(2560, 0, 100, 100)).resolve(&geometry), None);
+    }
+}
@@ -1,5 +1,7 @@
 use std::ffi;
 use std::io;
+use std::sync::atomic::{self, AtomicUsize};
+use std::time;
 
 pub struct ShmFd<S: AsRef<str>> {
     fd: libc::c_int,
@@ -38,15 +40,9 @@ impl<S: AsRef<str>> ShmFd<S> {
         self.unlinked
     }
 
+    #[inline(always)]
     pub fn truncate(&mut self, size: libc::off_t) -> io::Result<()> {
-        unsafe {
-            let status = libc::ftruncate(self.fd, size);
-            if status == 0 {
-                Ok(())
-            } else {
-                Err(io::Error::last_os_error())
-            }
-        }
+        truncate(self.fd, size)
     }
 
     #[inline(always)]
@@ -63,13 +59,126 @@ impl<S: AsRef<str>> ShmFd<S> {
 impl<S: AsRef<str>> Drop for ShmFd<S> {
     fn drop(&mut self) {
         println!("obs_wlroots: ShmFd::Drop: {}", self.as_raw());
-        unsafe {
-            if !self.is_unlinked() {
-                self.unlink()
-                    .expect(&format!("Error unlinking ShmFd({})", self.as_raw()));
+        // Creation may have failed to unlink already, so this is only worth a message
+        if !self.is_unlinked() {
+            if let Err(e) = self.unlink() {
+                println!("obs_wlroots: error unlinking {}: {}", self.path_ref(), e);
             }
+        }
+        unsafe {
             libc::close(self.fd);
+        }
+    }
+}
+
+/// File descriptor returned by `memfd_create`
+pub struct MemFd(libc::c_int);
+
+impl MemFd {
+    pub fn create<S: AsRef<str>>(name: S, flags: libc::c_uint) -> io::Result<MemFd> {
+        let name = ffi::CString::new(name.as_ref())
+            .expect("invalid utf8 string");
+        let fd = unsafe {
+            libc::memfd_create(name.as_ptr(), flags)
         };
+        if fd >= 0 {
+            Ok(MemFd(fd))
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// Adds `seals` (`F_SEAL_*`), only possible if created with `MFD_ALLOW_SEALING`
+    pub fn add_seals(&mut self, seals: libc::c_int) -> io::Result<()> {
+        let status = unsafe {
+            libc::fcntl(self.0, libc::F_ADD_SEALS, seals)
+        };
+        if status == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    #[inline(always)]
+    pub fn as_raw(&self) -> libc::c_int {
+        self.0
+    }
+}
+
+impl Drop for MemFd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+/// Anonymous shared memory to back a wl_shm pool. Prefers a sealed memfd, falling back to a
+/// uniquely named, immediately unlinked POSIX shm object where memfd is unavailable.
+pub enum AnonymousShm {
+    MemFd(MemFd),
+    Shm(ShmFd<String>),
+}
+
+impl AnonymousShm {
+    /// Creates a file of `size` bytes. For memfd the size is sealed so nobody else holding the fd
+    /// (i.e. the compositor) can shrink it under our mapping.
+    pub fn new(name: &str, size: libc::off_t) -> io::Result<AnonymousShm> {
+        match MemFd::create(name, libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) {
+            Ok(mut fd) => {
+                truncate(fd.as_raw(), size)?;
+                fd.add_seals(libc::F_SEAL_SHRINK | libc::F_SEAL_SEAL)?;
+                Ok(AnonymousShm::MemFd(fd))
+            },
+            Err(ref e) if e.raw_os_error() == Some(libc::ENOSYS) => {
+                let mut fd = AnonymousShm::open_unique(name)?;
+                fd.unlink()?;
+                fd.truncate(size)?;
+                Ok(AnonymousShm::Shm(fd))
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    fn open_unique(name: &str) -> io::Result<ShmFd<String>> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        const ATTEMPTS: usize = 16;
+
+        let pid = unsafe { libc::getpid() };
+        for _ in 0..ATTEMPTS {
+            let nanos = time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map(|d| d.subsec_nanos())
+                .unwrap_or(0);
+            let count = COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
+            let path = format!("/{}-{}-{:08x}-{}", name, pid, nanos, count);
+            match ShmFd::open(path, libc::O_CREAT | libc::O_EXCL | libc::O_RDWR | libc::O_CLOEXEC, 0o600) {
+                Ok(fd) => return Ok(fd),
+                Err(ref e) if e.raw_os_error() == Some(libc::EEXIST) => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::from_raw_os_error(libc::EEXIST))
+    }
+
+    #[inline(always)]
+    pub fn as_raw(&self) -> libc::c_int {
+        match self {
+            AnonymousShm::MemFd(fd) => fd.as_raw(),
+            AnonymousShm::Shm(fd) => fd.as_raw(),
+        }
+    }
+}
+
+fn truncate(fd: libc::c_int, size: libc::off_t) -> io::Result<()> {
+    let status = unsafe {
+        libc::ftruncate(fd, size)
+    };
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
     }
 }
 
@@ -1,11 +1,13 @@
 use std::cell::Cell;
 use std::collections::BTreeMap;
+use std::env;
+use std::io;
 use std::mem;
+use std::rc::Rc;
 use std::sync::{
     Arc,
     RwLock,
     Mutex,
-    mpsc,
 };
 use std::sync::atomic::{self, AtomicBool};
 use std::thread;
@@ -21,56 +23,147 @@ use wayland_client::{
     Main
 };
 use wayland_client::protocol::wl_buffer::WlBuffer;
-use wayland_client::protocol::wl_output::WlOutput;
+use wayland_client::protocol::wl_output::{self, WlOutput};
 use wayland_client::protocol::wl_shm::{self, WlShm};
 use wayland_client::protocol::wl_shm_pool::WlShmPool;
 use wayland_protocols::unstable::xdg_output::v1::client::zxdg_output_manager_v1::ZxdgOutputManagerV1;
-use wayland_protocols::unstable::xdg_output::v1::client::zxdg_output_v1;
-use wayland_protocols::wlr::unstable::screencopy::v1::client::zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1};
-use wayland_protocols::wlr::unstable::screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1;
-use crate::shm::ShmFd;
+use wayland_protocols::unstable::xdg_output::v1::client::zxdg_output_v1::{self, ZxdgOutputV1};
+use wayland_protocols::wlr::unstable::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1;
+use crate::protocols::wlr_screencopy::client::zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1};
+use crate::protocols::wlr_screencopy::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1;
+use crate::shm::AnonymousShm;
 use crate::mmap::MappedMemory;
+use crate::region::{self, Region, RegionSpec};
+use crate::composite::{self, Canvas};
+use crate::format::ShmFormat;
+use crate::mailbox::Mailbox;
+use crate::output::{OutputId, OutputInfo, OutputMatch};
+use crate::toplevel::{ExtToplevels, FocusTracker, Window, WindowRule, WindowRules, WlrToplevels};
+
+mod backend;
+mod combined;
+mod image_copy;
+mod placeholder;
+
+use self::backend::{CaptureBackend, CaptureMethod, CaptureTarget, OutputTransform, TargetOutput};
+use self::placeholder::{Placeholder, PlaceholderImage};
 
 pub struct WlrSource {
-    display: Arc<Display>,
-    display_events: EventQueue,
-    wl_outputs: Arc<RwLock<BTreeMap<u32, Arc<Main<WlOutput>>>>>,
-    outputs: BTreeMap<u32, Arc<RwLock<WlrOutput>>>,
-    output_manager: Main<ZxdgOutputManagerV1>,
+    /// Socket from the settings, empty for `$WAYLAND_DISPLAY`
+    socket: String,
+    /// The compositor, or why we couldn't connect to it
+    connection: Result<Connection, String>,
+    /// When to try connecting again while disconnected
+    retry_at: time::Instant,
+    retry_delay: time::Duration,
+    /// When to try capturing again after the capture thread ended on its own
+    restart_at: Option<time::Instant>,
+    /// Whether the texture holds the current placeholder rather than a captured frame
+    placeholder_shown: bool,
+    placeholder_image: PlaceholderImage,
+    snapshot: Mutex<PropertiesSnapshot>,
+    config: CaptureConfig,
+    /// Ids and names of the outputs the capture target was last resolved against
+    known_outputs: Vec<(u32, Option<OutputId>)>,
     video_thread: Option<VideoThread>,
+    /// Whether the capture thread has nothing to capture, as of the last `video_tick`
+    idle: bool,
     source_handle: obs::source::SourceHandle,
+    mode: SourceMode,
+    texture: Option<obs::gs::Texture>,
+    flip: bool,
     last_width: u32,
     last_height: u32,
 }
 
-impl WlrSource {
-    fn update_xdg(&mut self) {
-        for (&id, ref wl_output) in self.wl_outputs.read().unwrap().iter() {
-            self.outputs.remove(&id);
-            self.outputs.insert(id, WlrOutput::new(wl_output, &self.output_manager));
-        }
-        self.display_events.sync_roundtrip(|_, _| {})
-            .expect("Error waiting on display events");
-    }
+/// Value of the `output` setting that follows the focused window instead of naming an output
+const FOCUSED_OUTPUT: &'static str = "*focused";
+
+/// Value of the `output` setting that combines all outputs
+const ALL_OUTPUTS: &'static str = "*all";
+
+/// Number of window rules in the properties, tried in order
+const WINDOW_RULE_COUNT: usize = 3;
+
+/// First delay before reconnecting to the compositor, doubled after every failed attempt
+const RECONNECT_MIN_DELAY: time::Duration = time::Duration::from_millis(500);
+
+const RECONNECT_MAX_DELAY: time::Duration = time::Duration::from_secs(30);
+
+/// What the user asked to capture, kept so the target can be resolved again as outputs come and go
+#[derive(Clone, Default)]
+struct CaptureConfig {
+    output: String,
+    output_match: OutputMatch,
+    region: Option<RegionSpec>,
+    window_rules: WindowRules,
+    show_cursor: bool,
+    buffer_count: usize,
+    method: CaptureMethod,
+    placeholder: Placeholder,
+    /// Size to show the placeholder at, instead of the size of the last captured frame
+    placeholder_size: Option<(u32, u32)>,
 }
 
-impl obs::source::Source for WlrSource {
-    const ID: &'static [u8] = b"obs_wlroots\0";
-    const NAME: &'static [u8] = b"wlroots capture\0";
+/// What the properties show about the connection. OBS calls `get_properties` on the UI thread, while
+/// the connection's event queue and everything its handlers update belong to the graphics thread,
+/// which refreshes this in `update` and `video_tick`.
+#[derive(Clone, Default)]
+struct PropertiesSnapshot {
+    status: String,
+    reports_focus: bool,
+    /// Label and setting value of every output that has a name
+    outputs: Vec<(String, String)>,
+    globals: Vec<(u32, String, u32)>,
+    /// Label and setting value of every open window, `None` if windows can't be captured
+    windows: Option<Vec<(String, String)>>,
+    /// App ids of the open windows
+    app_ids: Vec<String>,
+    matched_window: String,
+}
 
-    fn create(settings: &mut obs_sys::obs_data_t, source: &mut obs_sys::obs_source_t) -> Result<WlrSource, String> {
-        use obs::data::ObsData;
+/// How captured frames are handed to OBS
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SourceMode {
+    /// Frames are pulled by `render` on the graphics thread
+    Sync,
+    /// Frames are pushed into OBS's async video pipeline by the capture thread
+    Async,
+}
+
+/// A connection to the compositor, and the outputs and windows it advertises
+struct Connection {
+    /// Name of the socket we're connected to, e.g. `wayland-1`
+    name: String,
+    display: Arc<Display>,
+    display_events: EventQueue,
+    global_manager: GlobalManager,
+    /// Outputs announced by the registry, picked up by `sync_outputs`
+    wl_outputs: Arc<RwLock<BTreeMap<u32, Arc<RwLock<WlrOutput>>>>>,
+    outputs: BTreeMap<u32, Arc<RwLock<WlrOutput>>>,
+    output_manager: Main<ZxdgOutputManagerV1>,
+    /// Open windows, if the compositor lets us list them. This is the list window capture picks
+    /// windows from too.
+    toplevels: Option<ExtToplevels>,
+    /// Open windows as wlr-foreign-toplevel-management reports them, preferred for listing
+    wlr_toplevels: Option<WlrToplevels>,
+}
 
-        let display = settings.get_str("display")
-            .map(|name| name.into_owned())
-            .filter(|name| name.len() != 0)
-            .map(|display_name| Display::connect_to_name(display_name))
-            .unwrap_or_else(Display::connect_to_env)
-            .map_err(|e| format!("Error connecting to wayland display: {}", e))?;
+impl Connection {
+    /// Connects to `socket`, or to `$WAYLAND_DISPLAY` if it's empty
+    fn new(socket: &str) -> Result<Connection, String> {
+        let (display, name) = if socket.len() != 0 {
+            (Display::connect_to_name(socket), socket.to_string())
+        } else {
+            let name = env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| "wayland-0".into());
+            (Display::connect_to_env(), name)
+        };
+        let display = display
+            .map_err(|e| format!("Error connecting to wayland display {}: {}", name, e))?;
         let mut display_events = display.create_event_queue();
         let source_display = (*display).clone().attach(display_events.get_token());
 
-        let outputs: Arc<RwLock<BTreeMap<u32, Arc<Main<WlOutput>>>>> = Arc::new(RwLock::new(BTreeMap::new()));
+        let outputs: Arc<RwLock<BTreeMap<u32, Arc<RwLock<WlrOutput>>>>> = Arc::new(RwLock::new(BTreeMap::new()));
 
         let gm_outputs = outputs.clone();
         let global_manager = GlobalManager::new_with_cb(&source_display, move |evt, registry| {
@@ -80,7 +173,7 @@ impl obs::source::Source for WlrSource {
                     match interface.as_ref() {
                         <WlOutput as Interface>::NAME => {
                             let output = registry.bind::<WlOutput>(version, id);
-                            outputs.insert(id, Arc::new(output));
+                            outputs.insert(id, WlrOutput::new(output));
                         },
                         _ => {},
                     }
@@ -94,79 +187,764 @@ impl obs::source::Source for WlrSource {
             .map_err(|e| format!("Error waiting on display events: {}", e))?;
         let output_manager = global_manager.instantiate_exact::<ZxdgOutputManagerV1>(2)
             .map_err(|e| format!("Error instantiating {}: {}", <ZxdgOutputManagerV1 as Interface>::NAME, e))?;
+        let toplevels = ExtToplevels::new(&global_manager);
+        let wlr_toplevels = WlrToplevels::new(&global_manager);
         display_events.sync_roundtrip(|_, _| {})
             .map_err(|e| format!("Error waiting on display events: {}", e))?;
-        
-        let mut ret = WlrSource {
+
+        let mut ret = Connection {
+            name: name,
             display: Arc::new(display),
             display_events: display_events,
+            global_manager: global_manager,
             wl_outputs: outputs,
-            output_manager: output_manager,
             outputs: BTreeMap::new(),
+            output_manager: output_manager,
+            toplevels: toplevels,
+            wlr_toplevels: wlr_toplevels,
+        };
+        ret.sync_outputs();
+        ret.display_events.sync_roundtrip(|_, _| {})
+            .map_err(|e| format!("Error waiting on display events: {}", e))?;
+        println!("obs_wlroots: {}", ret.status());
+        Ok(ret)
+    }
+
+    /// Tracks outputs added or removed since the last call. The names of new outputs arrive with
+    /// later events.
+    fn sync_outputs(&mut self) {
+        let wl_outputs = self.wl_outputs.read().unwrap();
+        let removed: Vec<u32> = self.outputs.keys()
+            .cloned()
+            .filter(|id| !wl_outputs.contains_key(id))
+            .collect();
+        for id in removed {
+            if let Some(output) = self.outputs.remove(&id) {
+                println!("obs_wlroots: output removed: {}", output.read().unwrap().info.label());
+            }
+        }
+        for (&id, output) in wl_outputs.iter() {
+            if !self.outputs.contains_key(&id) {
+                WlrOutput::attach_xdg(output, &self.output_manager);
+                self.outputs.insert(id, output.clone());
+            }
+        }
+    }
+
+    fn output_names(&self) -> Vec<(u32, Option<OutputId>)> {
+        self.outputs.iter()
+            .map(|(&id, output)| (id, output.read().unwrap().info.id()))
+            .collect()
+    }
+
+    /// Open windows to pick from, or `None` if windows can't be captured. Both lists identify
+    /// windows by app id and title, so a window listed by one is found in the other.
+    fn windows(&self) -> Option<Vec<Window>> {
+        let toplevels = self.toplevels.as_ref()?;
+        Some(match self.wlr_toplevels.as_ref() {
+            Some(wlr_toplevels) => wlr_toplevels.windows(),
+            None => toplevels.windows(),
+        })
+    }
+
+    /// Whether the compositor reports which window has focus
+    fn reports_focus(&self) -> bool {
+        self.global_manager.list().iter()
+            .any(|&(_, ref interface, _)| interface == <ZwlrForeignToplevelManagerV1 as Interface>::NAME)
+    }
+
+    /// One line summary, e.g. "connected to wayland-1, wlr-screencopy v3, 2 outputs"
+    fn status(&self) -> String {
+        let globals = self.global_manager.list();
+        let mut parts = vec![format!("connected to {}", self.name)];
+        for method in CaptureMethod::ALL.iter() {
+            let version = method.protocol()
+                .and_then(|protocol| globals.iter().find(|&&(_, ref interface, _)| interface == protocol))
+                .map(|&(_, _, version)| version);
+            if let Some(version) = version {
+                parts.push(format!("{} v{}", method.description(), version));
+            }
+        }
+        match self.outputs.len() {
+            1 => parts.push("1 output".into()),
+            n => parts.push(format!("{} outputs", n)),
+        }
+        parts.join(", ")
+    }
+}
+
+impl WlrSource {
+    /// Resolves the configured target against the current outputs, and starts, restarts or stops
+    /// capturing as needed
+    fn apply(&mut self) {
+        let connection = match self.connection.as_ref() {
+            Ok(connection) => connection,
+            Err(_) => {
+                self.stop_capture();
+                return;
+            },
+        };
+        self.known_outputs = connection.output_names();
+        let config = &self.config;
+        let target = if !config.window_rules.is_empty() {
+            Some(CaptureTarget::Toplevel(config.window_rules.clone()))
+        } else if config.output == FOCUSED_OUTPUT || config.output == ALL_OUTPUTS {
+            let outputs = connection.outputs.values()
+                .map(|output| output.read().unwrap().target())
+                .collect();
+            if config.output == FOCUSED_OUTPUT {
+                Some(CaptureTarget::FocusedOutput(outputs))
+            } else {
+                Some(CaptureTarget::AllOutputs(outputs))
+            }
+        } else {
+            let (outputs, ids): (Vec<TargetOutput>, Vec<OutputId>) = connection.outputs.values()
+                .filter_map(|output| {
+                    let output = output.read().unwrap();
+                    output.info.id().map(|id| (output.target(), id))
+                })
+                .unzip();
+            config.output.parse::<OutputId>().ok()
+                .and_then(|id| config.output_match.find(&id, &ids))
+                .map(|i| CaptureTarget::Output {
+                    output: outputs[i].clone(),
+                    region: config.region,
+                })
+        };
+
+        // Cursor overlay is picked up by the next frame request, so only a change of target
+        // needs a new capture thread
+        if let (Some(video_thread), Some(target)) = (self.video_thread.as_ref(), target.as_ref()) {
+            if video_thread.is_capturing(target, config.method, config.buffer_count) {
+                video_thread.set_show_cursor(config.show_cursor);
+                return;
+            }
+        }
+        match target {
+            Some(target) => {
+                mem::drop(self.video_thread.take());
+                let display = connection.display.clone();
+                self.video_thread = Some(VideoThread::new(target, config.method, config.show_cursor, config.buffer_count, self.mode, self.source_handle, display));
+                self.idle = false;
+            },
+            None => {
+                if self.video_thread.is_some() {
+                    println!("obs_wlroots: output {} is gone, waiting for it to come back", config.output);
+                }
+                self.stop_capture();
+            },
+        }
+    }
+
+    /// Stops capturing and shows the placeholder instead
+    fn stop_capture(&mut self) {
+        if self.video_thread.is_none() {
+            return;
+        }
+        let idle = self.idle;
+        mem::drop(self.video_thread.take());
+        self.idle = false;
+        // An idle thread already left the placeholder on screen
+        if !idle {
+            self.remember_frame_size();
+            self.show_placeholder();
+        }
+    }
+
+    /// Whether frames from the capture thread are what's shown, rather than the placeholder
+    #[inline(always)]
+    fn is_capturing(&self) -> bool {
+        self.video_thread.is_some() && !self.idle
+    }
+
+    /// Keeps the size of the last captured frame for the placeholder, before it replaces the frame
+    fn remember_frame_size(&mut self) {
+        if self.mode == SourceMode::Async {
+            // Only OBS knows the size of the frames the capture thread sent it
+            let (width, height) = (self.source_handle.width(), self.source_handle.height());
+            if width != 0 && height != 0 {
+                self.last_width = width;
+                self.last_height = height;
+            }
+        }
+    }
+
+    /// Shows the current placeholder. Sync sources draw it in `render`, async ones have to be sent
+    /// it.
+    fn show_placeholder(&mut self) {
+        self.placeholder_shown = false;
+        if self.mode != SourceMode::Async {
+            return;
+        }
+        match self.placeholder_frame() {
+            Some(frame) => self.source_handle.output_video(Some(&frame.1)),
+            // OBS keeps showing the last frame unless told otherwise
+            None if self.config.placeholder != Placeholder::LastFrame => self.source_handle.output_video(None),
+            None => {},
+        }
+    }
+
+    /// Connects to `socket`, stopping any capture on the previous connection first
+    fn connect(&mut self, socket: String) {
+        self.socket = socket;
+        self.retry_delay = RECONNECT_MIN_DELAY;
+        self.disconnect("Not connected".into());
+        self.try_connect();
+    }
+
+    /// Drops the connection, retrying after the current backoff delay
+    fn disconnect(&mut self, reason: String) {
+        self.stop_capture();
+        self.restart_at = None;
+        self.known_outputs.clear();
+        self.connection = Err(reason);
+        self.retry_at = time::Instant::now() + self.retry_delay;
+    }
+
+    /// Connects to the configured socket, backing off further if that fails. The caller re-resolves
+    /// the capture target.
+    fn try_connect(&mut self) {
+        match Connection::new(&self.socket) {
+            Ok(connection) => {
+                self.connection = Ok(connection);
+                self.retry_delay = RECONNECT_MIN_DELAY;
+            },
+            Err(e) => {
+                println!("obs_wlroots: {}, retrying in {:?}", e, self.retry_delay);
+                self.connection = Err(e);
+                self.retry_at = time::Instant::now() + self.retry_delay;
+                self.retry_delay = (self.retry_delay * 2).min(RECONNECT_MAX_DELAY);
+            },
+        }
+    }
+
+    /// Refreshes what `get_properties` shows from the connection and the settings
+    fn refresh_snapshot(&self) {
+        let mut snapshot = PropertiesSnapshot::default();
+        let connection = match self.connection.as_ref() {
+            Ok(connection) => connection,
+            Err(e) => {
+                snapshot.status = format!("not connected: {}", e);
+                *self.snapshot.lock().unwrap() = snapshot;
+                return;
+            },
+        };
+        snapshot.status = connection.status();
+        snapshot.reports_focus = connection.reports_focus();
+        snapshot.globals = connection.global_manager.list();
+
+        let (ids, labels): (Vec<OutputId>, Vec<String>) = connection.outputs.values()
+            .filter_map(|output| {
+                let output = output.read().unwrap();
+                output.info.id().map(|id| (id, output.info.label()))
+            })
+            .unzip();
+        // The output the settings resolve to keeps the stored value, so it still shows as selected
+        // after moving to another connector
+        let config = &self.config;
+        let selected = config.output.parse::<OutputId>().ok()
+            .and_then(|id| config.output_match.find(&id, &ids));
+        snapshot.outputs = ids.iter().zip(labels.into_iter()).enumerate()
+            .map(|(i, (id, label))| {
+                let value = if selected == Some(i) {
+                    config.output.clone()
+                } else {
+                    id.to_string()
+                };
+                (label, value)
+            })
+            .collect();
+
+        if let Some(windows) = connection.windows() {
+            let mut app_ids: Vec<String> = windows.iter()
+                .map(|window| window.app_id.clone())
+                .filter(|app_id| app_id.len() != 0)
+                .collect();
+            app_ids.dedup();
+            snapshot.app_ids = app_ids;
+            snapshot.windows = Some(windows.iter()
+                .map(|window| (window.label(), window.to_string()))
+                .collect());
+            snapshot.matched_window = if config.window_rules.is_empty() {
+                "Matched window: none (capturing output)".into()
+            } else {
+                match config.window_rules.best_match(&windows) {
+                    Some(window) => format!("Matched window: {}", window.label()),
+                    None => "Matched window: none (waiting for a match)".into(),
+                }
+            };
+        }
+        *self.snapshot.lock().unwrap() = snapshot;
+    }
+
+    /// The configured placeholder size, or else the size of the last captured frame. Before
+    /// anything was captured the placeholder fills the canvas.
+    fn placeholder_size(&self) -> Option<(u32, u32)> {
+        self.config.placeholder_size
+            .or_else(|| {
+                Some((self.last_width, self.last_height))
+                    .filter(|&(width, height)| width != 0 && height != 0)
+            })
+            .or_else(obs::base_resolution)
+    }
+
+    /// The placeholder drawn at `placeholder_size`. `None` for the last frame, which is already on
+    /// screen, and while the size is unknown.
+    fn placeholder_frame(&self) -> Option<FrameData> {
+        let (width, height) = self.placeholder_size()?;
+        let canvas = self.config.placeholder.draw(width, height, &self.placeholder_image)?;
+        let meta = FrameMetadata::new(wl_shm::Format::Argb8888.to_raw(), canvas.width(), canvas.height(), canvas.stride());
+        let memory = FrameMemory::Converted(canvas.into_vec());
+        Some(unsafe {
+            FrameData::new(memory, &meta, obs_sys::video_format::VIDEO_FORMAT_BGRA, obs::os_gettime_ns(), None)
+        })
+    }
+
+    fn new(settings: &mut obs_sys::obs_data_t, source: &mut obs_sys::obs_source_t, mode: SourceMode) -> Result<WlrSource, String> {
+        use obs::data::ObsData;
+
+        let socket = settings.get_str("display")
+            .map(|name| name.trim().to_string())
+            .unwrap_or_default();
+        let mut ret = WlrSource {
+            socket: String::new(),
+            connection: Err("Not connected".into()),
+            retry_at: time::Instant::now(),
+            retry_delay: RECONNECT_MIN_DELAY,
+            restart_at: None,
+            placeholder_shown: false,
+            placeholder_image: PlaceholderImage::default(),
+            snapshot: Mutex::new(PropertiesSnapshot::default()),
+            config: CaptureConfig::default(),
+            known_outputs: Vec::new(),
             video_thread: None,
+            idle: false,
             source_handle: obs::source::SourceHandle::new(source as *mut obs_sys::obs_source_t),
+            mode: mode,
+            texture: None,
+            flip: false,
             last_width: 0,
             last_height: 0,
         };
-        ret.update_xdg();
-        ret.update(settings);
+        // A source that can't connect yet stays around, so the socket can be fixed in its properties
+        ret.connect(socket);
+        obs::source::Source::update(&mut ret, settings);
         Ok(ret)
     }
+}
+
+impl obs::source::Source for WlrSource {
+    const ID: &'static [u8] = b"obs_wlroots\0";
+    const NAME: &'static [u8] = b"wlroots capture\0";
+
+    fn create(settings: &mut obs_sys::obs_data_t, source: &mut obs_sys::obs_source_t) -> Result<WlrSource, String> {
+        WlrSource::new(settings, source, SourceMode::Sync)
+    }
+
+    fn get_defaults(settings: &mut obs_sys::obs_data_t) {
+        use obs::data::ObsData;
+
+        settings.set_default_bool("show_cursor", true);
+        settings.set_default_int("buffer_count", 2);
+        settings.set_default_string("capture_method", CaptureMethod::Auto.id());
+        settings.set_default_string("output_match", OutputMatch::Monitor.id());
+        settings.set_default_string("placeholder", "color");
+        settings.set_default_int("placeholder_color", 0xff000000);
+    }
 
     fn update(&mut self, settings: &mut obs_sys::obs_data_t) {
-        use std::borrow::Cow;
         use obs::data::ObsData;
 
-        let current_output = self.outputs.iter()
-            .map(|(_, output)| output.read().unwrap())
-            .find(|output| {
-                output.name() == settings.get_str("output").unwrap_or(Cow::Borrowed(""))
-            })
-            .map(|output| output.handle.clone());
-        mem::drop(self.video_thread.take());
-        self.video_thread = current_output.map(|handle| VideoThread::new(handle, self.source_handle, self.display.clone()));
+        let socket = settings.get_str("display")
+            .map(|name| name.trim().to_string())
+            .unwrap_or_default();
+        if socket != self.socket {
+            self.connect(socket);
+        }
+        let region = if settings.get_bool("capture_region") {
+            region_from_settings(settings)
+        } else {
+            None
+        };
+        self.config = CaptureConfig {
+            output: settings.get_str("output")
+                .map(|s| s.into_owned())
+                .unwrap_or_default(),
+            output_match: settings.get_str("output_match")
+                .and_then(|id| OutputMatch::from_id(&id))
+                .unwrap_or_default(),
+            region: region,
+            window_rules: window_rules_from_settings(settings),
+            show_cursor: settings.get_bool("show_cursor"),
+            buffer_count: settings.get_int("buffer_count").max(2) as usize,
+            method: settings.get_str("capture_method")
+                .and_then(|id| CaptureMethod::from_id(&id))
+                .unwrap_or_default(),
+            placeholder: Placeholder::from_settings(settings),
+            placeholder_size: Some((settings.get_int("placeholder_width") as u32, settings.get_int("placeholder_height") as u32))
+                .filter(|&(width, height)| width != 0 && height != 0),
+        };
+        self.placeholder_image.update(&self.config.placeholder);
+        self.apply();
+        self.refresh_snapshot();
+        if !self.is_capturing() {
+            // The placeholder settings may have changed
+            self.show_placeholder();
+        }
+    }
+
+    fn video_tick(&mut self, _seconds: f32) {
+        if self.connection.is_err() {
+            if time::Instant::now() >= self.retry_at {
+                self.try_connect();
+                if self.connection.is_ok() {
+                    self.apply();
+                }
+                self.refresh_snapshot();
+            }
+            return;
+        }
+        let connection = self.connection.as_mut().unwrap();
+        // Outputs and windows come and go with events that are only read here. This is also where
+        // a compositor that went away shows up.
+        let dispatched = match dispatch_timeout(&connection.display, &mut connection.display_events, time::Duration::from_millis(0)) {
+            Ok(dispatched) => dispatched,
+            Err(e) => {
+                let reason = format!("Lost connection to {}: {}", connection.name, e);
+                println!("obs_wlroots: {}", reason);
+                self.disconnect(reason);
+                self.refresh_snapshot();
+                return;
+            },
+        };
+        connection.sync_outputs();
+        if connection.output_names() != self.known_outputs {
+            self.apply();
+        }
+        if dispatched > 0 {
+            self.refresh_snapshot();
+        }
+        if let Some(video_thread) = self.video_thread.as_ref().filter(|t| t.is_finished()) {
+            // A thread that ran for a while is worth retrying right away
+            if video_thread.started.elapsed() > RECONNECT_MAX_DELAY {
+                self.retry_delay = RECONNECT_MIN_DELAY;
+            }
+            println!("obs_wlroots: capture ended, retrying in {:?}", self.retry_delay);
+            self.stop_capture();
+            self.restart_at = Some(time::Instant::now() + self.retry_delay);
+            self.retry_delay = (self.retry_delay * 2).min(RECONNECT_MAX_DELAY);
+        } else if self.restart_at.map(|at| time::Instant::now() >= at).unwrap_or(false) {
+            self.restart_at = None;
+            self.apply();
+        }
+        // The thread keeps running while it has nothing to capture, e.g. until a matching window
+        // opens, and the placeholder fills in meanwhile
+        let idle = self.video_thread.as_ref().map(VideoThread::is_idle).unwrap_or(false);
+        if idle != self.idle {
+            if idle {
+                println!("obs_wlroots: nothing to capture, showing the placeholder");
+                // Whatever was captured last is stale by the time there's something to capture again
+                mem::drop(self.video_thread.as_ref().and_then(VideoThread::latest_frame));
+                self.remember_frame_size();
+                self.idle = true;
+                self.show_placeholder();
+            } else {
+                println!("obs_wlroots: capturing again");
+                self.idle = false;
+            }
+        }
     }
 
     fn get_properties(&mut self) -> obs::Properties {
         use obs::properties::PropertyList;
 
+        // Runs on the UI thread, so everything about the connection comes from the snapshot
+        let snapshot = self.snapshot.lock().unwrap().clone();
         let mut props = obs::Properties::new();
+        props.add_text("display", "Wayland display (empty for $WAYLAND_DISPLAY)");
+        props.add_info("status", &format!("Status: {}", snapshot.status));
+
         let mut output_list = props.add_string_list("output", "Output");
+        output_list.add_item("All outputs", ALL_OUTPUTS);
+        if snapshot.reports_focus {
+            output_list.add_item("Follow focused window", FOCUSED_OUTPUT);
+        }
+        for &(ref label, ref value) in snapshot.outputs.iter() {
+            output_list.add_item(label, value);
+        }
+        let mut match_list = props.add_string_list("output_match", "Find output by");
+        for output_match in OutputMatch::ALL.iter() {
+            match_list.add_item(output_match.description(), output_match.id());
+        }
 
-        for (_, ref output) in self.outputs.iter() {
-            let output = output.read().unwrap();
-            let name = output.name();
-            output_list.add_item(name, name);
+        if let Some(windows) = snapshot.windows.as_ref() {
+            // The picked window is matched exactly before any of the rules, which catch it (or
+            // others) once its title changed or it was restarted
+            let mut window_list = props.add_string_list("window", "Window");
+            window_list.add_item("None (capture output)", "");
+            for &(ref label, ref value) in windows.iter() {
+                window_list.add_item(label, value);
+            }
+            // Picking an app id from a rule's list only fills that in, titles change too often to
+            // match on by default
+            for n in 1..=WINDOW_RULE_COUNT {
+                let mut app_id_list = props.add_editable_string_list(&format!("window{}_app_id", n), &format!("Window rule {}: app id (empty for any)", n));
+                for app_id in snapshot.app_ids.iter() {
+                    app_id_list.add_item(app_id, app_id);
+                }
+                props.add_text(&format!("window{}_title", n), &format!("Window rule {}: title (empty for any)", n));
+                props.add_bool(&format!("window{}_regex", n), &format!("Window rule {}: match regular expressions", n));
+            }
+            props.add_info("matched_window", &snapshot.matched_window);
         }
 
+        let mut method_list = props.add_string_list("capture_method", "Capture method");
+        for method in CaptureMethod::ALL.iter().filter(|method| method.is_supported(&snapshot.globals)) {
+            method_list.add_item(method.description(), method.id());
+        }
+
+        props.add_bool("show_cursor", "Show cursor");
+        props.add_int("buffer_count", "Capture buffers", 2, 8, 1);
+        props.add_bool("capture_region", "Capture region");
+        props.add_int("region_x", "Region X", 0, 16384, 1);
+        props.add_int("region_y", "Region Y", 0, 16384, 1);
+        props.add_int("region_width", "Region width", 0, 16384, 1);
+        props.add_int("region_height", "Region height", 0, 16384, 1);
+        props.add_text("region_geometry", "Region geometry (slurp \"x,y wxh\", overrides the above)");
+
+        let mut placeholder_list = props.add_string_list("placeholder", "When nothing can be captured, show");
+        for &(id, description) in Placeholder::KINDS.iter() {
+            placeholder_list.add_item(description, id);
+        }
+        props.add_color("placeholder_color", "Placeholder color");
+        props.add_file_path("placeholder_image", "Placeholder image", "Images (*.png *.jpg *.jpeg *.bmp *.gif *.webp);;All files (*.*)");
+        props.add_int("placeholder_width", "Placeholder width (0 for the last captured size)", 0, 16384, 1);
+        props.add_int("placeholder_height", "Placeholder height (0 for the last captured size)", 0, 16384, 1);
+
         props
     }
 }
 
+/// Reads the window rules from `settings`, in priority order. The window picked from the list comes
+/// first, then the configured rules. Each rule has its own app id and title settings, so patterns
+/// can contain anything.
+fn window_rules_from_settings(settings: &obs_sys::obs_data_t) -> WindowRules {
+    use obs::data::ObsData;
+
+    let mut ret = WindowRules::default();
+    let window = settings.get_str("window")
+        .filter(|s| s.len() != 0)
+        .and_then(|s| {
+            s.parse::<Window>()
+                .map_err(|e| println!("obs_wlroots: ignoring window: {}", e))
+                .ok()
+        });
+    if let Some(window) = window {
+        ret.push(WindowRule::exact(&window));
+    }
+    for n in 1..=WINDOW_RULE_COUNT {
+        let get = |field: &str| {
+            settings.get_str(format!("window{}_{}", n, field))
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        match WindowRule::new(&get("app_id"), &get("title"), settings.get_bool(format!("window{}_regex", n))) {
+            Ok(Some(rule)) => ret.push(rule),
+            Ok(None) => {},
+            Err(e) => println!("obs_wlroots: ignoring window rule {}: {}", n, e),
+        }
+    }
+    ret
+}
+
+/// Reads the capture region from `settings`. A non-empty slurp-style `region_geometry` is taken to
+/// be in global layout coordinates and wins over the output-local `region_*` integers.
+fn region_from_settings(settings: &obs_sys::obs_data_t) -> Option<RegionSpec> {
+    use obs::data::ObsData;
+
+    let geometry = settings.get_str("region_geometry")
+        .map(|s| s.into_owned())
+        .filter(|s| s.trim().len() != 0);
+    if let Some(geometry) = geometry {
+        return geometry.parse::<Region>()
+            .map(RegionSpec::Layout)
+            .map_err(|e| println!("obs_wlroots: ignoring region: {}", e))
+            .ok();
+    }
+    let region = Region::new(
+        settings.get_int("region_x") as i32,
+        settings.get_int("region_y") as i32,
+        settings.get_int("region_width") as i32,
+        settings.get_int("region_height") as i32
+    );
+    if region.is_empty() {
+        println!("obs_wlroots: ignoring empty region: {}", region);
+        None
+    } else {
+        Some(RegionSpec::Output(region))
+    }
+}
+
 impl obs::source::VideoSource for WlrSource {
     fn width(&self) -> u32 {
-        self.last_width
+        if self.is_capturing() {
+            self.last_width
+        } else {
+            self.placeholder_size().map(|(width, _)| width).unwrap_or(0)
+        }
     }
 
     fn height(&self) -> u32 {
-        self.last_height
+        if self.is_capturing() {
+            self.last_height
+        } else {
+            self.placeholder_size().map(|(_, height)| height).unwrap_or(0)
+        }
     }
 
     fn render(&mut self) {
-        if let Some(video_thread) = self.video_thread.as_mut() {
-            let FrameData(_mem, mut source_frame) = video_thread.receiver_mut().recv().unwrap();
+        if !self.is_capturing() {
+            // Capture stopped, e.g. because the output or the compositor went away, or there is
+            // nothing to capture, e.g. no window matches
+            if !self.placeholder_shown {
+                self.placeholder_shown = true;
+                if self.config.placeholder != Placeholder::LastFrame {
+                    self.texture = None;
+                    if let Some(FrameData(_mem, source_frame, _)) = self.placeholder_frame() {
+                        self.flip = false;
+                        if let Err(e) = self.upload(&source_frame, None) {
+                            println!("obs_wlroots: {}", e);
+                        }
+                    }
+                }
+            }
+            // The last frame is stretched to the placeholder size too
+            let size = self.placeholder_size();
+            if let (Some(texture), Some((width, height))) = (self.texture.as_mut(), size) {
+                obs::source::obs_source_draw(texture, 0, 0, width, height, self.flip);
+            }
+            return;
+        }
+        let frame = self.video_thread.as_ref()
+            .and_then(|video_thread| video_thread.latest_frame());
+        if let Some(FrameData(_mem, source_frame, damage)) = frame {
+            // Damage is relative to the last frame, not to a placeholder drawn since
+            let damage = damage.filter(|_| !self.placeholder_shown);
+            self.placeholder_shown = false;
             self.last_width = source_frame.width;
             self.last_height = source_frame.height;
-            let mut texture = obs::gs::Texture::from(&mut source_frame);
-            obs::source::obs_source_draw(&mut texture, 0, 0, 0, 0, source_frame.flip);
+            self.flip = source_frame.flip;
+            if let Err(e) = self.upload(&source_frame, damage.as_ref().map(|d| d.as_slice())) {
+                // Don't leave a stale frame on screen
+                self.texture = None;
+                println!("obs_wlroots: {}", e);
+            }
+        }
+        // With nothing new from the compositor, the last frame is still what's on screen
+        if let Some(texture) = self.texture.as_mut() {
+            obs::source::obs_source_draw(texture, 0, 0, 0, 0, self.flip);
         }
     }
 }
 
+impl WlrSource {
+    /// Uploads a frame to the persistent texture, re-creating it only if the frame's size or format
+    /// changed. Only the `damage`d parts are copied unless the texture is new, the damage is unknown
+    /// or it covers most of the frame anyway. Must be called from the graphics thread.
+    fn upload(&mut self, frame: &obs_sys::obs_source_frame, damage: Option<&[Region]>) -> Result<(), String> {
+        let color_format = obs::gs::translate_format(frame.format)
+            .ok_or_else(|| format!("Unsupported video format: {:?}", &frame.format))?;
+        let reusable = self.texture.as_ref()
+            .map(|t| t.width() == frame.width && t.height() == frame.height && t.color_format() == color_format)
+            .unwrap_or(false);
+        if !reusable {
+            self.texture = None;
+            let texture = obs::gs::Texture::new_dynamic(frame.width, frame.height, color_format)
+                .ok_or_else(|| format!("Error creating {}x{} texture", frame.width, frame.height))?;
+            self.texture = Some(texture);
+        }
+        let bounds = Region::new(0, 0, frame.width as i32, frame.height as i32);
+        let partial = damage
+            .filter(|_| reusable)
+            .and_then(|damage| region::clip_damage(damage, &bounds));
+        let texture = self.texture.as_mut().unwrap();
+        if let Some(damage) = partial {
+            let bpp = obs::gs::format_bytes_per_pixel(color_format).unwrap() as usize;
+            if let Some(mut mapped) = texture.map() {
+                for r in damage.iter() {
+                    let start = (r.x as usize) * bpp;
+                    let len = (r.width as usize) * bpp;
+                    for y in r.y..(r.y + r.height) {
+                        let src = unsafe {
+                            let row = frame.data[0].offset((y as isize) * (frame.linesize[0] as isize));
+                            std::slice::from_raw_parts(row.add(start), len)
+                        };
+                        mapped.row_mut(y as u32)[start..(start + len)].copy_from_slice(src);
+                    }
+                }
+                return Ok(());
+            }
+        }
+        unsafe {
+            texture.set_image(frame.data[0], frame.linesize[0], false);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WlrSource {
+    fn drop(&mut self) {
+        mem::drop(self.video_thread.take());
+        if let Some(texture) = self.texture.take() {
+            let _ctx = obs::gs::GraphicsContext::enter();
+            mem::drop(texture);
+        }
+    }
+}
+
+/// Variant of `WlrSource` that feeds OBS's async video pipeline instead of rendering frames itself
+pub struct WlrAsyncSource(WlrSource);
+
+impl obs::source::Source for WlrAsyncSource {
+    const ID: &'static [u8] = b"obs_wlroots_async\0";
+    const NAME: &'static [u8] = b"wlroots capture (async)\0";
+
+    fn create(settings: &mut obs_sys::obs_data_t, source: &mut obs_sys::obs_source_t) -> Result<WlrAsyncSource, String> {
+        WlrSource::new(settings, source, SourceMode::Async).map(WlrAsyncSource)
+    }
+
+    fn get_defaults(settings: &mut obs_sys::obs_data_t) {
+        <WlrSource as obs::source::Source>::get_defaults(settings);
+    }
+
+    fn update(&mut self, settings: &mut obs_sys::obs_data_t) {
+        obs::source::Source::update(&mut self.0, settings);
+    }
+
+    fn get_properties(&mut self) -> obs::Properties {
+        obs::source::Source::get_properties(&mut self.0)
+    }
+
+    fn video_tick(&mut self, seconds: f32) {
+        obs::source::Source::video_tick(&mut self.0, seconds)
+    }
+}
+
+impl obs::source::AsyncVideoSource for WlrAsyncSource {}
+
 pub struct VideoThread {
     thread: Option<thread::JoinHandle<()>>,
     running: Arc<AtomicBool>,
-    receiver: Option<mpsc::Receiver<FrameData>>,
+    /// Set by the thread when it exits, whether it was asked to or not
+    finished: Arc<AtomicBool>,
+    started: time::Instant,
+    show_cursor: Arc<AtomicBool>,
+    target: CaptureTarget,
+    method: CaptureMethod,
+    buffer_count: usize,
+    mailbox: Option<Mailbox<FrameData>>,
+    /// Set by the thread while it has nothing to capture
+    idle: Arc<AtomicBool>,
 }
 
 #[no_mangle]
@@ -180,58 +958,263 @@ fn obs_wlroots_create_event_queue(display: &Display) -> EventQueue {
     display.create_event_queue()
 }
 
+/// Waits at most `timeout` for events on `events` and dispatches them. Unlike a roundtrip this
+/// sleeps until the compositor actually has something for us.
+fn dispatch_timeout(display: &Display, events: &mut EventQueue, timeout: time::Duration) -> io::Result<u32> {
+    let dispatched = events.dispatch_pending(|_, _| {})?;
+    if dispatched > 0 {
+        return Ok(dispatched);
+    }
+    display.flush()?;
+    if let Some(guard) = events.prepare_read() {
+        let mut fd = libc::pollfd {
+            fd: display.get_connection_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let status = unsafe {
+            libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int)
+        };
+        if status < 0 {
+            guard.cancel();
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        } else if status == 0 {
+            guard.cancel();
+        } else {
+            guard.read_events()?;
+        }
+    }
+    events.dispatch_pending(|_, _| {})
+}
+
+/// Sets the flag it holds when dropped, so the capture thread flags itself as finished on every
+/// way out
+struct FinishedFlag(Arc<AtomicBool>);
+
+impl Drop for FinishedFlag {
+    fn drop(&mut self) {
+        self.0.store(true, atomic::Ordering::Relaxed);
+    }
+}
+
+/// How long the capture thread sleeps on the compositor before re-checking whether it should stop
+const VIDEO_THREAD_POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
 impl VideoThread {
-    fn new(output: WlOutput, source_handle: obs::source::SourceHandle, display: Arc<Display>) -> VideoThread {
+    fn new(target: CaptureTarget, method: CaptureMethod, show_cursor: bool, buffer_count: usize, mode: SourceMode, source_handle: obs::source::SourceHandle, display: Arc<Display>) -> VideoThread {
         let running = Arc::new(AtomicBool::new(true));
         let running_ret = running.clone();
+        let finished = Arc::new(AtomicBool::new(false));
+        let finished_ret = finished.clone();
+        let show_cursor = Arc::new(AtomicBool::new(show_cursor));
+        let show_cursor_ret = show_cursor.clone();
+        let idle = Arc::new(AtomicBool::new(false));
+        let idle_ret = idle.clone();
+        let target_ret = target.clone();
         let builder = thread::Builder::new()
             .name("obs-wlroots".into());
-        let (sender, receiver) = mpsc::sync_channel(1);
+        let mailbox = match mode {
+            SourceMode::Sync => Some(Mailbox::new()),
+            SourceMode::Async => None,
+        };
+        let sink = FrameSink {
+            mailbox: mailbox.clone(),
+            source_handle: source_handle,
+        };
         let t = builder.spawn(move || {
+            let _finished = FinishedFlag(finished);
             let mut events = obs_wlroots_create_event_queue(display.as_ref());
             let video_display = (**display).clone().attach(events.get_token());
             let global_manager = GlobalManager::new(&video_display);
-            events.sync_roundtrip(|_, _| {})
-                .expect("Error waiting on display events");
-            let screencopy_manager = global_manager.instantiate_exact::<ZwlrScreencopyManagerV1>(1)
-                .expect(&format!("Error instantiating {}", <ZwlrScreencopyManagerV1 as Interface>::NAME));
-            let shm = global_manager.instantiate_exact::<WlShm>(1)
-                .expect(&format!("Error instantiating {}", <WlShm as Interface>::NAME));
-            events.sync_roundtrip(|_, _| {})
-                .expect("Error waiting on display events");
-            let frame = WlrFrame::new((*shm).clone(), source_handle, sender);
+            // The source notices a lost connection on its own queue and restarts capture once it
+            // has reconnected, so errors here just end the thread
+            if let Err(e) = events.sync_roundtrip(|_, _| {}) {
+                println!("obs_wlroots: error waiting on display events: {}", e);
+                return;
+            }
+            let shm = match global_manager.instantiate_exact::<WlShm>(1) {
+                Ok(shm) => shm,
+                Err(e) => {
+                    println!("obs_wlroots: error instantiating {}: {}", <WlShm as Interface>::NAME, e);
+                    return;
+                },
+            };
+
+            let globals = global_manager.list();
+            let bind = |target: &CaptureTarget| {
+                let method = method.resolve(&globals, target);
+                println!("obs_wlroots: capturing with {}", method.description());
+                method.bind(&global_manager, target, (*shm).clone(), sink.clone(), buffer_count)
+                    .map_err(|e| println!("obs_wlroots: {}", e))
+                    .ok()
+            };
+            // Following focus, the backend is bound once we know where the focused window is
+            let mut focus = None;
+            let mut backend = None;
+            let mut focus_outputs = Vec::new();
+            match target {
+                CaptureTarget::FocusedOutput(ref outputs) => match WlrToplevels::new(&global_manager) {
+                    Some(toplevels) => {
+                        focus = Some(FocusTracker::new(toplevels));
+                        focus_outputs = outputs.clone();
+                    },
+                    None => {
+                        println!("obs_wlroots: compositor doesn't report the focused window");
+                        return;
+                    },
+                },
+                ref target => match bind(target) {
+                    Some(b) => backend = Some(b),
+                    None => return,
+                },
+            }
+            if let Err(e) = events.sync_roundtrip(|_, _| {}) {
+                println!("obs_wlroots: error waiting on display events: {}", e);
+                return;
+            }
             let mut start = time::Instant::now();
             let mut frame_count = 0u64;
 
-            while running.load(atomic::Ordering::Relaxed) || frame.waiting.load(atomic::Ordering::Relaxed) {
-                if WlrFrame::handle_output(&frame, &screencopy_manager, &output) {
-                    frame_count = frame_count + 1;
+            let mut connected = true;
+            while running.load(atomic::Ordering::Relaxed) {
+                if let Some(output) = focus.as_mut().and_then(FocusTracker::poll) {
+                    println!("obs_wlroots: focus moved, switching outputs");
+                    // A damage-gated frame on the old output may never finish, don't wait for it
+                    if let Some(mut backend) = backend.take() {
+                        backend.cancel();
+                    }
+                    backend = bind(&CaptureTarget::Output {
+                        output: TargetOutput::find(&focus_outputs, output),
+                        region: None,
+                    });
+                }
+                if let Some(backend) = backend.as_mut() {
+                    if backend.is_stopped() {
+                        println!("obs_wlroots: capture stopped");
+                        break;
+                    }
+                    if backend.request_frame(show_cursor.load(atomic::Ordering::Relaxed)) {
+                        frame_count = frame_count + 1;
+                    }
+                }
+                // Following focus, nothing is captured until some output has focus
+                let has_target = backend.as_ref().map(|backend| backend.has_target()).unwrap_or(false);
+                idle.store(!has_target, atomic::Ordering::Relaxed);
+                if let Err(e) = dispatch_timeout(&display, &mut events, VIDEO_THREAD_POLL_INTERVAL) {
+                    println!("obs_wlroots: error waiting on display events: {}", e);
+                    connected = false;
+                    break;
                 }
-                events.sync_roundtrip(|_, _| {})
-                    .expect("Error waiting on display events");
                 if start.elapsed().as_millis() > 1000 {
                     println!("obs_wlroots: fps = {}", frame_count);
                     start = time::Instant::now();
                     frame_count = 0;
                 }
             }
-            events.sync_roundtrip(|_, _| {})
-                .expect("Error waiting on disply events");
-            obs_wlroots_video_thread_done(&frame);
-            mem::drop(frame);
+            // A frame still in flight is abandoned rather than waited for. With copy_with_damage it
+            // isn't finished until something on screen changes.
+            if let Some(mut backend) = backend {
+                if backend.is_waiting() {
+                    println!("obs_wlroots: abandoning frame in flight");
+                }
+                backend.cancel();
+            }
+            mem::drop(focus);
+            if connected {
+                if let Err(e) = events.sync_roundtrip(|_, _| {}) {
+                    println!("obs_wlroots: error waiting on display events: {}", e);
+                }
+            }
             mem::drop(events);
             mem::drop(running);
         }).unwrap();
         VideoThread {
             thread: Some(t),
             running: running_ret,
-            receiver: Some(receiver),
+            finished: finished_ret,
+            started: time::Instant::now(),
+            show_cursor: show_cursor_ret,
+            target: target_ret,
+            method: method,
+            buffer_count: buffer_count,
+            mailbox: mailbox,
+            idle: idle_ret,
+        }
+    }
+
+    /// Whether this thread is already capturing `target` with `method` into `buffer_count` buffers
+    fn is_capturing(&self, target: &CaptureTarget, method: CaptureMethod, buffer_count: usize) -> bool {
+        self.target.is_same(target) && self.method == method && self.buffer_count == buffer_count
+    }
+
+    /// Whether the thread ended on its own, e.g. because capture stopped or failed
+    #[inline(always)]
+    fn is_finished(&self) -> bool {
+        self.finished.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Whether the thread is running but has nothing to capture, e.g. no window matches the rules
+    #[inline(always)]
+    fn is_idle(&self) -> bool {
+        self.idle.load(atomic::Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    fn set_show_cursor(&self, show_cursor: bool) {
+        self.show_cursor.store(show_cursor, atomic::Ordering::Relaxed);
+    }
+
+    /// Newest frame captured since the last call, never waits on the compositor
+    #[inline(always)]
+    fn latest_frame(&self) -> Option<FrameData> {
+        self.mailbox.as_ref().and_then(Mailbox::take)
+    }
+}
+
+/// Tracks the logical geometry of an output through xdg_output
+struct OutputGeometry {
+    xdg_output: Main<ZxdgOutputV1>,
+    logical: Rc<Cell<Option<Region>>>,
+}
+
+impl OutputGeometry {
+    fn new(output_manager: &ZxdgOutputManagerV1, output: &WlOutput) -> OutputGeometry {
+        let xdg_output = output_manager.get_xdg_output(output);
+        let logical = Rc::new(Cell::new(None));
+        let pending = Cell::new(Region::new(0, 0, 0, 0));
+        let handler_logical = logical.clone();
+        xdg_output.assign_mono(move |_, evt| {
+            match evt {
+                zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                    pending.set(Region { x: x, y: y, ..pending.get() });
+                },
+                zxdg_output_v1::Event::LogicalSize { width, height } => {
+                    pending.set(Region { width: width, height: height, ..pending.get() });
+                },
+                zxdg_output_v1::Event::Done => {
+                    handler_logical.set(Some(pending.get()));
+                },
+                _ => {},
+            }
+        });
+        OutputGeometry {
+            xdg_output: xdg_output,
+            logical: logical,
         }
     }
 
     #[inline(always)]
-    fn receiver_mut(&mut self) -> &mut mpsc::Receiver<FrameData> {
-        self.receiver.as_mut().unwrap()
+    fn get(&self) -> Option<Region> {
+        self.logical.get()
+    }
+}
+
+impl Drop for OutputGeometry {
+    fn drop(&mut self) {
+        self.xdg_output.destroy();
     }
 }
 
@@ -240,43 +1223,75 @@ impl Drop for VideoThread {
         println!("obs_wlroots: VideoThread::drop");
         if let Some(t) = self.thread.take() {
             self.running.store(false, atomic::Ordering::Relaxed);
-            mem::drop(self.receiver.take());
-            t.join().unwrap();
+            if t.join().is_err() {
+                println!("obs_wlroots: VideoThread: capture thread panicked");
+            }
         }
     }
 }
 
-const WLR_FRAME_SHM_PATH: &'static str = "/obs_wlroots";
-
 struct WlrBuffer {
     pool: Main<WlShmPool>,
     buffer: Main<WlBuffer>,
-    fd: ShmFd<&'static str>,
-    size: usize,
+    fd: AnonymousShm,
+    mapping: Arc<MappedMemory>,
+    meta: FrameMetadata,
+    in_use: Arc<AtomicBool>,
 }
 
 impl WlrBuffer {
-    fn new(shm: &Attached<WlShm>, frame: &WlrFrame) -> WlrBuffer {
-        let size = frame.size();
-        let mut fd = ShmFd::open(WLR_FRAME_SHM_PATH, libc::O_CREAT | libc::O_RDWR, 0).unwrap();
-        fd.unlink()
-            .expect("error unlinking ShmFd");
-        fd.truncate(size as libc::off_t)
-            .expect("error truncating ShmFd");
+    fn new(shm: &Attached<WlShm>, meta: &FrameMetadata) -> Result<WlrBuffer, String> {
+        let format = meta.buffer_format()
+            .ok_or_else(|| format!("Unknown wl_shm format: {:#x}", meta.format))?;
+        let size = meta.size();
+        let fd = AnonymousShm::new("obs_wlroots", size as libc::off_t)
+            .map_err(|e| format!("Error creating shm buffer: {}", e))?;
+        let mapping = unsafe {
+            MappedMemory::new(size, libc::PROT_READ, libc::MAP_SHARED, fd.as_raw(), 0)
+                .map_err(|e| format!("Error mapping shm buffer: {}", e))?
+        };
         let pool  = shm.create_pool(fd.as_raw(), size as i32);
-        let frame_meta = frame.metadata.get();
-        let buffer = pool.create_buffer(0, frame_meta.width as i32, frame_meta.height as i32, frame_meta.stride as i32, frame.buffer_format().unwrap());
-        WlrBuffer {
+        let buffer = pool.create_buffer(0, meta.width as i32, meta.height as i32, meta.stride as i32, format);
+        Ok(WlrBuffer {
             pool: pool,
             buffer: buffer,
             fd: fd,
-            size: size,
-        }
+            mapping: Arc::new(mapping),
+            meta: *meta,
+            in_use: Arc::new(AtomicBool::new(false)),
+        })
     }
 
+    /// Whether this buffer has the layout the compositor asked for in `meta`
     #[inline(always)]
-    pub fn size(&self) -> usize {
-        self.size
+    fn matches(&self, meta: &FrameMetadata) -> bool {
+        self.meta.format == meta.format && self.meta.width == meta.width && self.meta.height == meta.height && self.meta.stride == meta.stride
+    }
+
+    #[inline(always)]
+    fn is_in_use(&self) -> bool {
+        self.in_use.load(atomic::Ordering::Acquire)
+    }
+
+    /// Marks the buffer busy until the returned lease is dropped
+    fn acquire(&self) -> BufferLease {
+        self.in_use.store(true, atomic::Ordering::Release);
+        BufferLease(self.in_use.clone())
+    }
+
+    /// Shared view of the buffer contents, valid for as long as any view or the buffer is alive
+    #[inline(always)]
+    pub fn mapping(&self) -> Arc<MappedMemory> {
+        self.mapping.clone()
+    }
+}
+
+/// Keeps a `WlrBuffer` from being handed to the compositor again while its contents are in use
+struct BufferLease(Arc<AtomicBool>);
+
+impl Drop for BufferLease {
+    fn drop(&mut self) {
+        self.0.store(false, atomic::Ordering::Release);
     }
 }
 
@@ -288,12 +1303,111 @@ impl Drop for WlrBuffer {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+/// Up to `depth` shm buffers for the compositor to copy frames into. There are at least two, so
+/// the next frame can be captured while the render side holds on to the last one.
+struct BufferRing {
+    shm: Attached<WlShm>,
+    buffers: Vec<WlrBuffer>,
+    depth: usize,
+}
+
+impl BufferRing {
+    fn new(shm: Attached<WlShm>, depth: usize) -> BufferRing {
+        BufferRing {
+            shm: shm,
+            buffers: Vec::with_capacity(depth.max(2)),
+            depth: depth.max(2),
+        }
+    }
+
+    /// Whether `acquire` would find a buffer, whatever the layout. Free buffers of another layout
+    /// make room for new ones.
+    fn has_free(&self) -> bool {
+        self.buffers.len() < self.depth || self.buffers.iter().any(|b| !b.is_in_use())
+    }
+
+    /// Finds or creates a free buffer with the layout in `meta`, or `None` if every buffer is still
+    /// being consumed
+    fn acquire(&mut self, meta: &FrameMetadata) -> Result<Option<(&WlrBuffer, BufferLease)>, String> {
+        // Buffers of a previous layout are dropped as soon as OBS is done with them
+        self.buffers.retain(|b| b.matches(meta) || b.is_in_use());
+        let index = self.buffers.iter()
+            .position(|b| b.matches(meta) && !b.is_in_use());
+        let index = match index {
+            Some(i) => i,
+            None if self.buffers.len() < self.depth => {
+                println!("obs_wlroots: creating buffer {} of {}", self.buffers.len() + 1, self.depth);
+                self.buffers.push(WlrBuffer::new(&self.shm, meta)?);
+                self.buffers.len() - 1
+            },
+            None => return Ok(None),
+        };
+        let buffer = &self.buffers[index];
+        Ok(Some((buffer, buffer.acquire())))
+    }
+}
+
+/// Where finished frames go: the mailbox `render` reads from, or OBS's async pipeline
+#[derive(Clone)]
+struct FrameSink {
+    mailbox: Option<Mailbox<FrameData>>,
+    source_handle: obs::source::SourceHandle,
+}
+
+impl FrameSink {
+    /// Hands a frame the compositor copied into `buf` to OBS
+    fn deliver(&self, buf: Arc<MappedMemory>, lease: BufferLease, meta: &FrameMetadata, timestamp: u64, damage: Option<Vec<Region>>) {
+        let shm_format = match meta.shm_format() {
+            Some(f) => f,
+            None => return,
+        };
+        // Converted frames no longer need the buffer, so the lease ends here
+        let memory = if shm_format.needs_conversion() {
+            FrameMemory::Converted(shm_format.convert(buf.as_slice(), meta.width as usize, meta.height as usize, meta.stride as usize))
+        } else {
+            FrameMemory::Mapped(buf, lease)
+        };
+        if meta.is_rotated() {
+            self.post_upright(memory, meta, shm_format.video_format, timestamp);
+        } else {
+            self.post(memory, meta, shm_format.video_format, timestamp, damage);
+        }
+    }
+
+    /// Undoes the transform of a frame in `memory` and hands the result to OBS, which can only
+    /// flip frames by itself. Damage would have to be transformed too, so the whole frame counts as
+    /// changed.
+    fn post_upright(&self, memory: FrameMemory, meta: &FrameMetadata, video_format: obs_sys::video_format, timestamp: u64) {
+        let frame_data = unsafe { FrameData::new(memory, meta, video_format, timestamp, None) };
+        let (width, height) = composite::upright_size(meta.transform, meta.width, meta.height);
+        let mut canvas = Canvas::new(width, height);
+        if canvas.blit(&frame_data.1, &Region::new(0, 0, width as i32, height as i32), meta.transform).is_none() {
+            println!("obs_wlroots: can't transform frames in {:?}", video_format);
+            return;
+        }
+        let upright = FrameMetadata::new(wl_shm::Format::Argb8888.to_raw(), width, height, canvas.stride());
+        self.post(FrameMemory::Converted(canvas.into_vec()), &upright, obs_sys::video_format::VIDEO_FORMAT_BGRA, timestamp, None);
+    }
+
+    /// Hands a frame in `memory`, laid out as described by `meta`, to OBS
+    fn post(&self, memory: FrameMemory, meta: &FrameMetadata, video_format: obs_sys::video_format, timestamp: u64, damage: Option<Vec<Region>>) {
+        let frame_data = unsafe { FrameData::new(memory, meta, video_format, timestamp, damage) };
+        match self.mailbox.as_ref() {
+            Some(mailbox) => mailbox.post_with(frame_data, FrameData::merge_damage),
+            None => self.source_handle.output_video(Some(&frame_data.1)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
 struct FrameMetadata {
     format: u32,
     width: u32,
     height: u32,
     stride: u32,
+    flags: zwlr_screencopy_frame_v1::Flags,
+    /// Transform the buffer contents have relative to the upright image
+    transform: wl_output::Transform,
 }
 
 impl FrameMetadata {
@@ -303,6 +1417,8 @@ impl FrameMetadata {
             width: width,
             height: height,
             stride: stride,
+            flags: zwlr_screencopy_frame_v1::Flags::empty(),
+            transform: wl_output::Transform::Normal,
         }
     }
 
@@ -310,6 +1426,29 @@ impl FrameMetadata {
     pub fn size(&self) -> usize {
         (self.height as usize) * (self.stride as usize)
     }
+
+    /// Whether the buffer contents are upside-down and have to be flipped when drawn
+    #[inline(always)]
+    pub fn y_invert(&self) -> bool {
+        // A flip around the vertical axis and a half turn make for a vertical flip
+        self.flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert) != (self.transform == wl_output::Transform::Flipped180)
+    }
+
+    /// Whether the buffer contents are rotated or mirrored in a way OBS can't undo by flipping them
+    #[inline(always)]
+    pub fn is_rotated(&self) -> bool {
+        self.transform != wl_output::Transform::Normal && self.transform != wl_output::Transform::Flipped180
+    }
+
+    #[inline(always)]
+    pub fn buffer_format(&self) -> Option<wl_shm::Format> {
+        wl_shm::Format::from_raw(self.format)
+    }
+
+    #[inline(always)]
+    pub fn shm_format(&self) -> Option<ShmFormat> {
+        self.buffer_format().and_then(ShmFormat::from_wl_shm)
+    }
 }
 
 impl Default for FrameMetadata {
@@ -319,36 +1458,62 @@ impl Default for FrameMetadata {
             width: 0,
             height: 0,
             stride: 0,
+            flags: zwlr_screencopy_frame_v1::Flags::empty(),
+            transform: wl_output::Transform::Normal,
         }
     }
 }
 
 struct WlrFrame {
-    sender: mpsc::SyncSender<FrameData>,
+    sink: FrameSink,
     metadata: Cell<FrameMetadata>,
-    buffer: Mutex<Option<WlrBuffer>>,
-    shm: Attached<WlShm>,
+    buffers: Mutex<BufferRing>,
+    /// Whether frames are requested with `copy_with_damage`
+    with_damage: bool,
+    /// Transform of the output, which frames are captured with
+    transform: OutputTransform,
+    /// Damage reported for the in-flight frame
+    damage: Mutex<Vec<Region>>,
+    /// Mapping and lease of the buffer the in-flight frame is copied into
+    current: Mutex<Option<(Arc<MappedMemory>, BufferLease)>>,
+    /// The in-flight frame, kept so it can be abandoned
+    in_flight: Mutex<Option<ZwlrScreencopyFrameV1>>,
     waiting: AtomicBool,
-    source_handle: obs::source::SourceHandle,
+    /// Set when capturing can't go on, e.g. because no buffer could be allocated
+    failed: AtomicBool,
 }
 
 impl WlrFrame {
-    pub fn new(shm: Attached<WlShm>, source_handle: obs::source::SourceHandle, sender: mpsc::SyncSender<FrameData>) -> Arc<WlrFrame> {
+    pub fn new(shm: Attached<WlShm>, sink: FrameSink, buffer_count: usize, with_damage: bool, transform: OutputTransform) -> Arc<WlrFrame> {
         Arc::new(WlrFrame {
-            sender: sender,
+            sink: sink,
             metadata: Cell::new(FrameMetadata::default()),
-            buffer: Mutex::new(None),
-            shm: shm,
+            buffers: Mutex::new(BufferRing::new(shm, buffer_count)),
+            with_damage: with_damage,
+            transform: transform,
+            damage: Mutex::new(Vec::new()),
+            current: Mutex::new(None),
+            in_flight: Mutex::new(None),
             waiting: AtomicBool::new(false),
-            source_handle: source_handle
+            failed: AtomicBool::new(false),
         })
     }
 
-    pub fn handle_output(s: &Arc<WlrFrame>, screencopy_manager: &ZwlrScreencopyManagerV1, output: &WlOutput) -> bool {
+    pub fn handle_output(s: &Arc<WlrFrame>, screencopy_manager: &ZwlrScreencopyManagerV1, output: &WlOutput, region: Option<Region>, overlay_cursor: bool) -> bool {
+        // With every buffer still being consumed the frame would only be thrown away, wait for
+        // one to come back instead of asking the compositor again and again
+        if !s.buffers.lock().unwrap().has_free() {
+            return false;
+        }
         if !s.waiting.compare_and_swap(false, true, atomic::Ordering::AcqRel) {
             let handler = s.clone();
-            let frame = screencopy_manager.capture_output(1, output);
+            let overlay_cursor = overlay_cursor as i32;
+            let frame = match region {
+                Some(r) => screencopy_manager.capture_output_region(overlay_cursor, output, r.x, r.y, r.width, r.height),
+                None => screencopy_manager.capture_output(overlay_cursor, output),
+            };
             frame.assign_mono(move |obj, evt| handler.handle_frame_event(&obj, evt));
+            *s.in_flight.lock().unwrap() = Some((**frame).clone());
             return true;
         }
         false
@@ -358,47 +1523,111 @@ impl WlrFrame {
         use zwlr_screencopy_frame_v1::Event;
         match event {
             Event::Buffer { format, width, height, stride } => {
-                self.metadata.set(FrameMetadata::new(format, width, height, stride));
-                let mut buffer = self.buffer.lock().unwrap();
-                let buffer_size = buffer.as_ref().map(WlrBuffer::size);
-                if buffer_size.is_none() || buffer_size != Some(self.size()) {
-                    println!("obs_wlroots: re-creating buffer: had_previous = {}", !buffer_size.is_none());
-                    *buffer = Some(WlrBuffer::new(&self.shm, self));
+                let mut meta = FrameMetadata::new(format, width, height, stride);
+                meta.transform = self.transform.get();
+                self.metadata.set(meta);
+                if meta.shm_format().is_none() {
+                    // The compositor keeps offering the same format, asking again would only spin
+                    println!("obs_wlroots: unsupported wl_shm format: {:?} ({:#x}), stopping capture", meta.buffer_format(), format);
+                    self.fail(frame);
+                    return;
                 }
-                frame.copy(&buffer.as_ref().unwrap().buffer);
+                let mut buffers = self.buffers.lock().unwrap();
+                let (buffer, lease) = match buffers.acquire(&meta) {
+                    Ok(Some(b)) => b,
+                    Ok(None) => {
+                        // Every buffer is still being consumed, skip this frame. The next one isn't
+                        // requested until a buffer comes back.
+                        self.finish(frame);
+                        return;
+                    },
+                    Err(e) => {
+                        println!("obs_wlroots: {}", e);
+                        self.fail(frame);
+                        return;
+                    },
+                };
+                *self.current.lock().unwrap() = Some((buffer.mapping(), lease));
+                self.damage.lock().unwrap().clear();
+                if self.with_damage {
+                    frame.copy_with_damage(&buffer.buffer);
+                } else {
+                    frame.copy(&buffer.buffer);
+                }
+            },
+            Event::Damage { x, y, width, height } => {
+                let region = Region::new(x as i32, y as i32, width as i32, height as i32);
+                self.damage.lock().unwrap().push(region);
+            },
+            Event::Flags { flags } => {
+                let mut meta = self.metadata.get();
+                meta.flags = flags;
+                self.metadata.set(meta);
             },
-            Event::Ready { .. } => {
-                let buffer = self.buffer.lock().unwrap();
-                let buffer = buffer.as_ref().unwrap();
-                let buf = unsafe {
-                    MappedMemory::new(buffer.size(), libc::PROT_READ, libc::MAP_SHARED, buffer.fd.as_raw(), 0)
-                        .unwrap()
+            Event::Ready { tv_sec_hi, tv_sec_lo, tv_nsec } => {
+                let timestamp = presentation_time_ns(tv_sec_hi, tv_sec_lo, tv_nsec);
+                let (buf, lease) = match self.current.lock().unwrap().take() {
+                    Some(current) => current,
+                    None => {
+                        println!("obs_wlroots: compositor finished a frame that wasn't copied into a buffer");
+                        self.fail(frame);
+                        return;
+                    },
                 };
-                let meta = self.metadata.get();
-                // unsafe {
-                //     obs_sys::obs_source_output_video(self.source_handle.as_raw(), &source_frame);
-                // }
-                self.sender.send(unsafe { FrameData::new(buf, &meta) }).unwrap();
-
-                self.waiting.store(false, atomic::Ordering::Relaxed);
-                frame.destroy();
+                // Without copy_with_damage we know nothing about what changed
+                let damage = Some(mem::replace(&mut *self.damage.lock().unwrap(), Vec::new()))
+                    .filter(|_| self.with_damage);
+                self.sink.deliver(buf, lease, &self.metadata.get(), timestamp, damage);
+                self.finish(frame);
             },
             Event::Failed => {
-                self.waiting.store(false, atomic::Ordering::Relaxed);
-                frame.destroy();
+                mem::drop(self.current.lock().unwrap().take());
+                self.finish(frame);
             },
             _ => {},
         }
     }
 
-    fn size(&self) -> usize {
-        let meta = self.metadata.get();
-        meta.size()
+    /// Destroys the in-flight `frame`, making way for the next one
+    fn finish(&self, frame: &ZwlrScreencopyFrameV1) {
+        mem::drop(self.in_flight.lock().unwrap().take());
+        self.waiting.store(false, atomic::Ordering::Relaxed);
+        frame.destroy();
     }
 
-    #[inline(always)]
-    fn buffer_format(&self) -> Option<wl_shm::Format> {
-        wl_shm::Format::from_raw(self.metadata.get().format)
+    /// Destroys the in-flight `frame` and gives up on capturing
+    fn fail(&self, frame: &ZwlrScreencopyFrameV1) {
+        self.failed.store(true, atomic::Ordering::Relaxed);
+        self.finish(frame);
+    }
+
+    /// Abandons the in-flight frame, if any. With `copy_with_damage` the compositor holds on to it
+    /// until the output changes, which on a still screen may be never.
+    pub fn cancel(&self) {
+        if let Some(frame) = self.in_flight.lock().unwrap().take() {
+            frame.destroy();
+        }
+        mem::drop(self.current.lock().unwrap().take());
+        self.waiting.store(false, atomic::Ordering::Relaxed);
+    }
+}
+
+/// Converts a `Ready` timestamp to the OBS clock. The compositor reports it on CLOCK_MONOTONIC, so
+/// the frame's age on that clock is carried over to `os_gettime_ns`.
+fn presentation_time_ns(tv_sec_hi: u32, tv_sec_lo: u32, tv_nsec: u32) -> u64 {
+    let sec = ((tv_sec_hi as u64) << 32) | (tv_sec_lo as u64);
+    let presented = sec * 1_000_000_000 + (tv_nsec as u64);
+    let now = unsafe {
+        let mut ts: libc::timespec = mem::zeroed();
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+        (ts.tv_sec as u64) * 1_000_000_000 + (ts.tv_nsec as u64)
+    };
+    let obs_now = obs::os_gettime_ns();
+    if presented > now {
+        // Not on CLOCK_MONOTONIC, the best we can do is "now"
+        obs_now
+    } else {
+        obs_now.saturating_sub(now - presented)
     }
 }
 
@@ -408,24 +1637,152 @@ impl Drop for WlrFrame {
     }
 }
 
+/// Captures an output, or a region of it, through wlr-screencopy
+struct ScreencopyCapture {
+    manager: Main<ZwlrScreencopyManagerV1>,
+    output_manager: Main<ZxdgOutputManagerV1>,
+    geometry: OutputGeometry,
+    frame: Arc<WlrFrame>,
+    output: WlOutput,
+    region: Option<RegionSpec>,
+    last_geometry: Option<Region>,
+    /// Output-local region to capture, re-resolved whenever the output's geometry changes. `None`
+    /// while the configured region lies outside of the output.
+    capture_region: Option<Region>,
+}
+
+impl CaptureBackend for ScreencopyCapture {
+    fn bind(global_manager: &GlobalManager, target: &CaptureTarget, shm: Attached<WlShm>, sink: FrameSink, buffer_count: usize) -> Result<ScreencopyCapture, String> {
+        let (output, region) = match target {
+            &CaptureTarget::Output { ref output, region } => (output, region),
+            &CaptureTarget::FocusedOutput(_) => return Err("The focused output has to be resolved before capturing it".into()),
+            &CaptureTarget::AllOutputs(_) => return Err("Outputs have to be captured one by one to be combined".into()),
+            &CaptureTarget::Toplevel(_) => return Err("wlr-screencopy can only capture outputs".into()),
+        };
+        let manager = global_manager.instantiate_range::<ZwlrScreencopyManagerV1>(1, 2)
+            .map_err(|e| format!("Error instantiating {}: {}", <ZwlrScreencopyManagerV1 as Interface>::NAME, e))?;
+        // v2 lets the compositor hold on to the frame until something changed
+        let with_damage = manager.as_ref().version() >= 2;
+        let output_manager = global_manager.instantiate_exact::<ZxdgOutputManagerV1>(2)
+            .map_err(|e| format!("Error instantiating {}: {}", <ZxdgOutputManagerV1 as Interface>::NAME, e))?;
+        let geometry = OutputGeometry::new(&output_manager, &output.handle);
+        Ok(ScreencopyCapture {
+            manager: manager,
+            output_manager: output_manager,
+            geometry: geometry,
+            frame: WlrFrame::new(shm, sink, buffer_count, with_damage, output.transform.clone()),
+            output: output.handle.clone(),
+            region: region,
+            last_geometry: None,
+            capture_region: None,
+        })
+    }
+
+    fn request_frame(&mut self, overlay_cursor: bool) -> bool {
+        if let Some(spec) = self.region {
+            // Until the geometry is known there's no telling which part of the output to capture
+            let geometry = match self.geometry.get() {
+                Some(geometry) => geometry,
+                None => return false,
+            };
+            if self.last_geometry != Some(geometry) {
+                self.last_geometry = Some(geometry);
+                self.capture_region = spec.resolve(&geometry);
+                match self.capture_region {
+                    Some(r) => println!("obs_wlroots: capturing region {} of output at {}", r, geometry),
+                    None => println!("obs_wlroots: region {:?} lies outside of output at {}, waiting for the output to change", spec, geometry),
+                }
+            }
+            if self.capture_region.is_none() {
+                return false;
+            }
+        }
+        WlrFrame::handle_output(&self.frame, &self.manager, &self.output, self.capture_region, overlay_cursor)
+    }
+
+    #[inline(always)]
+    fn is_waiting(&self) -> bool {
+        self.frame.waiting.load(atomic::Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    fn is_stopped(&self) -> bool {
+        self.frame.failed.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Until the output's geometry is known the region is taken to be on it
+    #[inline(always)]
+    fn has_target(&self) -> bool {
+        self.region.is_none() || self.last_geometry.is_none() || self.capture_region.is_some()
+    }
+
+    #[inline(always)]
+    fn cancel(&mut self) {
+        self.frame.cancel();
+    }
+}
+
+impl Drop for ScreencopyCapture {
+    fn drop(&mut self) {
+        obs_wlroots_video_thread_done(&self.frame);
+        self.frame.cancel();
+        self.manager.destroy();
+        self.output_manager.destroy();
+    }
+}
+
 pub struct WlrOutput {
     handle: WlOutput,
-    name: Option<String>,
+    info: OutputInfo,
+    transform: OutputTransform,
 }
 
 impl WlrOutput {
-    pub fn new(handle: &WlOutput, output_manager: &ZxdgOutputManagerV1) -> Arc<RwLock<WlrOutput>> {
-        let xdg_output = output_manager.get_xdg_output(&handle);
+    /// Starts tracking a freshly bound output. Its make and model arrive right after binding, so
+    /// this has to happen in the registry callback.
+    pub fn new(handle: Main<WlOutput>) -> Arc<RwLock<WlrOutput>> {
         let ret = Arc::new(RwLock::new(WlrOutput {
-            handle: handle.clone(),
-            name: None,
+            handle: (**handle).clone(),
+            info: OutputInfo::default(),
+            transform: OutputTransform::default(),
         }));
         let output = ret.clone();
+        handle.assign_mono(move |_, evt| {
+            match evt {
+                wl_output::Event::Geometry { physical_width, physical_height, make, model, transform, .. } => {
+                    let mut output = output.write().unwrap();
+                    output.transform.set(transform);
+                    output.info.make = Some(make);
+                    output.info.model = Some(model);
+                    output.info.physical_size = Some((physical_width, physical_height));
+                },
+                _ => {},
+            }
+        });
+        ret
+    }
+
+    /// The output as a capture target
+    fn target(&self) -> TargetOutput {
+        TargetOutput {
+            handle: self.handle.clone(),
+            transform: self.transform.clone(),
+        }
+    }
+
+    /// Requests the output's connector name and description
+    pub fn attach_xdg(output: &Arc<RwLock<WlrOutput>>, output_manager: &ZxdgOutputManagerV1) {
+        let xdg_output = output_manager.get_xdg_output(&output.read().unwrap().handle);
+        let output = output.clone();
         xdg_output.assign_mono(move |handle, evt| {
             match evt {
                 zxdg_output_v1::Event::Name { name } => {
                     let mut output = output.write().unwrap();
-                    output.name = Some(name);
+                    output.info.name = Some(name);
+                },
+                zxdg_output_v1::Event::Description { description } => {
+                    let mut output = output.write().unwrap();
+                    output.info.description = Some(description);
                 },
                 zxdg_output_v1::Event::Done => {
                     handle.destroy();
@@ -433,30 +1790,41 @@ impl WlrOutput {
                 _ => {},
             }
         });
-        ret
     }
+}
+
+/// Pixel memory backing a `FrameData`
+enum FrameMemory {
+    /// The shm buffer the compositor copied into, returned to the pool when dropped
+    Mapped(Arc<MappedMemory>, BufferLease),
+    /// A copy converted to a format OBS understands
+    Converted(Vec<u8>),
+}
 
-    fn name(&self) -> &str {
-        self.name.as_ref()
-            .map(|s| s.as_ref())
-            .unwrap_or("<unknown>")
+impl FrameMemory {
+    fn as_raw(&self) -> *mut libc::c_void {
+        match self {
+            FrameMemory::Mapped(mem, _) => mem.as_raw(),
+            FrameMemory::Converted(v) => v.as_ptr() as *mut libc::c_void,
+        }
     }
 }
 
-struct FrameData(MappedMemory, obs_sys::obs_source_frame);
+/// A captured frame, plus the regions that changed since the previous frame (`None` if unknown)
+struct FrameData(FrameMemory, obs_sys::obs_source_frame, Option<Vec<Region>>);
 
 impl FrameData {
-    unsafe fn new(buf: MappedMemory, meta: &FrameMetadata) -> FrameData {
+    unsafe fn new(buf: FrameMemory, meta: &FrameMetadata, format: obs_sys::video_format, timestamp: u64, damage: Option<Vec<Region>>) -> FrameData {
         use std::ptr;
         let mut source_frame = obs_sys::obs_source_frame {
             data: [ptr::null_mut(); 8],
             linesize: [0; 8],
             width: meta.width,
             height: meta.height,
-            format: obs_sys::video_format::VIDEO_FORMAT_BGRA, // TODO: don't hard-code this
-            flip: true,
+            format: format,
+            flip: meta.y_invert(),
 
-            timestamp: 0,
+            timestamp: timestamp,
             color_matrix: [0f32; 16],
             full_range: false,
             color_range_min: [0f32; 3],
@@ -466,8 +1834,101 @@ impl FrameData {
         };
         source_frame.data[0] = mem::transmute(buf.as_raw());
         source_frame.linesize[0] = meta.stride;
-        FrameData(buf, source_frame)
+        FrameData(buf, source_frame, damage)
+    }
+
+    /// Folds the damage of a `previous` frame that was never rendered into `next`, so whoever
+    /// consumes `next` doesn't miss those changes
+    fn merge_damage(previous: FrameData, next: &mut FrameData) {
+        let FrameData(_, _, previous_damage) = previous;
+        next.2 = match (previous_damage, next.2.take()) {
+            (Some(mut a), Some(b)) => {
+                a.extend(b);
+                Some(a)
+            },
+            _ => None,
+        };
     }
 }
 
 unsafe impl Send for FrameData {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_data(flags: zwlr_screencopy_frame_v1::Flags) -> FrameData {
+        let mut meta = FrameMetadata::new(wl_shm::Format::Argb8888.to_raw(), 2, 2, 8);
+        meta.flags = flags;
+        let mem = FrameMemory::Converted(vec![0u8; meta.size()]);
+        unsafe {
+            FrameData::new(mem, &meta, obs_sys::video_format::VIDEO_FORMAT_BGRA, 0, None)
+        }
+    }
+
+    #[test]
+    fn y_inverted_frame_is_flipped() {
+        let FrameData(_, source_frame, _) = frame_data(zwlr_screencopy_frame_v1::Flags::YInvert);
+        assert!(source_frame.flip);
+    }
+
+    #[test]
+    fn upright_frame_is_not_flipped() {
+        let FrameData(_, source_frame, _) = frame_data(zwlr_screencopy_frame_v1::Flags::empty());
+        assert!(!source_frame.flip);
+    }
+
+    #[test]
+    fn flipped_transform_cancels_y_invert() {
+        let mut meta = FrameMetadata::new(0, 1, 1, 4);
+        meta.transform = wl_output::Transform::Flipped180;
+        assert!(meta.y_invert());
+        assert!(!meta.is_rotated());
+        meta.flags = zwlr_screencopy_frame_v1::Flags::YInvert;
+        assert!(!meta.y_invert());
+        meta.transform = wl_output::Transform::_90;
+        assert!(meta.is_rotated());
+    }
+
+    fn damaged_frame(damage: Option<Vec<Region>>) -> FrameData {
+        let FrameData(mem, source_frame, _) = frame_data(zwlr_screencopy_frame_v1::Flags::empty());
+        FrameData(mem, source_frame, damage)
+    }
+
+    #[test]
+    fn skipped_frames_pass_their_damage_on() {
+        let mailbox = Mailbox::new();
+        for i in 0..3 {
+            mailbox.post_with(damaged_frame(Some(vec![Region::new(i, 0, 1, 1)])), FrameData::merge_damage);
+        }
+        let FrameData(_, _, damage) = mailbox.take().unwrap();
+        assert_eq!(damage, Some(vec![Region::new(0, 0, 1, 1), Region::new(1, 0, 1, 1), Region::new(2, 0, 1, 1)]));
+
+        // Once taken, the next frame starts over
+        mailbox.post_with(damaged_frame(Some(vec![Region::new(5, 5, 1, 1)])), FrameData::merge_damage);
+        let FrameData(_, _, damage) = mailbox.take().unwrap();
+        assert_eq!(damage, Some(vec![Region::new(5, 5, 1, 1)]));
+    }
+
+    #[test]
+    fn unknown_damage_of_a_skipped_frame_spreads() {
+        let mailbox = Mailbox::new();
+        mailbox.post_with(damaged_frame(None), FrameData::merge_damage);
+        mailbox.post_with(damaged_frame(Some(vec![Region::new(0, 0, 1, 1)])), FrameData::merge_damage);
+        let FrameData(_, _, damage) = mailbox.take().unwrap();
+        assert_eq!(damage, None);
+
+        mailbox.post_with(damaged_frame(Some(vec![Region::new(0, 0, 1, 1)])), FrameData::merge_damage);
+        mailbox.post_with(damaged_frame(None), FrameData::merge_damage);
+        let FrameData(_, _, damage) = mailbox.take().unwrap();
+        assert_eq!(damage, None);
+    }
+
+    #[test]
+    fn new_metadata_resets_flags() {
+        let mut meta = FrameMetadata::new(0, 1, 1, 4);
+        meta.flags = zwlr_screencopy_frame_v1::Flags::YInvert;
+        assert!(meta.y_invert());
+        assert!(!FrameMetadata::new(0, 1, 1, 4).y_invert());
+    }
+}
@@ -0,0 +1,222 @@
+use wayland_client::{
+    Attached,
+    GlobalManager,
+    Interface
+};
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicU32};
+use wayland_client::protocol::wl_output::{self, WlOutput};
+use wayland_client::protocol::wl_shm::WlShm;
+use crate::protocols::wlr_screencopy::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1;
+use crate::protocols::ext_image_copy_capture::client::ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1;
+use crate::protocols::ext_image_capture_source::client::ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1;
+use crate::region::RegionSpec;
+use crate::toplevel::WindowRules;
+use super::FrameSink;
+use super::ScreencopyCapture;
+use super::image_copy::ImageCopyCapture;
+use super::combined::CombinedCapture;
+
+/// Transform of an output's contents, as last reported by wl_output. Shared with the capture
+/// thread, which has to undo it.
+#[derive(Clone, Default)]
+pub struct OutputTransform(Arc<AtomicU32>);
+
+impl OutputTransform {
+    pub fn get(&self) -> wl_output::Transform {
+        wl_output::Transform::from_raw(self.0.load(atomic::Ordering::Relaxed))
+            .unwrap_or(wl_output::Transform::Normal)
+    }
+
+    pub fn set(&self, transform: wl_output::Transform) {
+        self.0.store(transform.to_raw(), atomic::Ordering::Relaxed);
+    }
+}
+
+/// An output to capture
+#[derive(Clone)]
+pub struct TargetOutput {
+    pub handle: WlOutput,
+    pub transform: OutputTransform,
+}
+
+impl TargetOutput {
+    /// Finds `handle` among `outputs`. Outputs the source doesn't know about yet are taken to be
+    /// untransformed.
+    pub fn find(outputs: &[TargetOutput], handle: WlOutput) -> TargetOutput {
+        outputs.iter()
+            .find(|output| output.handle.as_ref().equals(handle.as_ref()))
+            .cloned()
+            .unwrap_or_else(|| TargetOutput {
+                handle: handle,
+                transform: OutputTransform::default(),
+            })
+    }
+}
+
+/// What the capture thread captures
+#[derive(Clone)]
+pub enum CaptureTarget {
+    /// An output, or a region of it
+    Output {
+        output: TargetOutput,
+        region: Option<RegionSpec>,
+    },
+    /// The window best matching the rules, re-evaluated by the capture thread as windows come and go
+    Toplevel(WindowRules),
+    /// Whichever of the outputs the focused window is on. The capture thread resolves this to an
+    /// `Output` target every time focus moves to another output.
+    FocusedOutput(Vec<TargetOutput>),
+    /// Every output, composited by their logical layout
+    AllOutputs(Vec<TargetOutput>),
+}
+
+impl CaptureTarget {
+    pub fn is_same(&self, other: &CaptureTarget) -> bool {
+        let same_outputs = |a: &[TargetOutput], b: &[TargetOutput]| {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.handle.as_ref().equals(b.handle.as_ref()))
+        };
+        match (self, other) {
+            (&CaptureTarget::Output { output: ref a, region: region_a }, &CaptureTarget::Output { output: ref b, region: region_b }) => {
+                a.handle.as_ref().equals(b.handle.as_ref()) && region_a == region_b
+            },
+            (&CaptureTarget::Toplevel(ref a), &CaptureTarget::Toplevel(ref b)) => a == b,
+            (&CaptureTarget::FocusedOutput(ref a), &CaptureTarget::FocusedOutput(ref b)) => same_outputs(a, b),
+            (&CaptureTarget::AllOutputs(ref a), &CaptureTarget::AllOutputs(ref b)) => same_outputs(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// A way of getting frames out of the compositor, driven by the capture thread. Backends tear down
+/// their protocol objects when dropped.
+pub(super) trait CaptureBackend {
+    /// Binds the globals the backend needs and prepares to capture `target`. Finished frames are
+    /// handed to `sink`, copied through a ring of `buffer_count` buffers.
+    fn bind(global_manager: &GlobalManager, target: &CaptureTarget, shm: Attached<WlShm>, sink: FrameSink, buffer_count: usize) -> Result<Self, String> where Self: Sized;
+
+    /// Requests the next frame unless one is still in flight. Returns whether a frame was requested.
+    fn request_frame(&mut self, overlay_cursor: bool) -> bool;
+
+    /// Whether a frame is in flight
+    fn is_waiting(&self) -> bool;
+
+    /// Abandons the frame in flight, if any, so the backend can be dropped or retargeted without
+    /// waiting for the compositor to finish it
+    fn cancel(&mut self);
+
+    /// Whether the capture ended for good, e.g. because the output went away or a buffer couldn't be
+    /// set up
+    fn is_stopped(&self) -> bool {
+        false
+    }
+
+    /// Whether there is anything to capture right now. A capture waiting for a matching window to
+    /// open, or for its region to be on the output again, has nothing to show until then.
+    fn has_target(&self) -> bool {
+        true
+    }
+}
+
+/// Capture backend selected by the user
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureMethod {
+    /// The best backend the compositor supports for the target
+    Auto,
+    Screencopy,
+    ImageCopy,
+}
+
+impl CaptureMethod {
+    pub const ALL: &'static [CaptureMethod] = &[
+        CaptureMethod::Auto,
+        CaptureMethod::Screencopy,
+        CaptureMethod::ImageCopy,
+    ];
+
+    /// Value stored in the source settings
+    pub fn id(&self) -> &'static str {
+        match self {
+            &CaptureMethod::Auto => "auto",
+            &CaptureMethod::Screencopy => "screencopy",
+            &CaptureMethod::ImageCopy => "image_copy",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            &CaptureMethod::Auto => "Automatic",
+            &CaptureMethod::Screencopy => "wlr-screencopy",
+            &CaptureMethod::ImageCopy => "ext-image-copy-capture",
+        }
+    }
+
+    /// Interface of the global this method captures with
+    pub fn protocol(&self) -> Option<&'static str> {
+        match self {
+            &CaptureMethod::Auto => None,
+            &CaptureMethod::Screencopy => Some(<ZwlrScreencopyManagerV1 as Interface>::NAME),
+            &CaptureMethod::ImageCopy => Some(<ExtImageCopyCaptureManagerV1 as Interface>::NAME),
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<CaptureMethod> {
+        CaptureMethod::ALL.iter()
+            .cloned()
+            .find(|method| method.id() == id)
+    }
+
+    /// Whether the compositor advertises everything this method needs in `globals`, as returned by
+    /// `GlobalManager::list`
+    pub fn is_supported(&self, globals: &[(u32, String, u32)]) -> bool {
+        let has = |name: &str| globals.iter().any(|&(_, ref interface, _)| interface == name);
+        match self {
+            &CaptureMethod::Auto => true,
+            &CaptureMethod::Screencopy => has(<ZwlrScreencopyManagerV1 as Interface>::NAME),
+            &CaptureMethod::ImageCopy => {
+                has(<ExtImageCopyCaptureManagerV1 as Interface>::NAME) && has(<ExtOutputImageCaptureSourceManagerV1 as Interface>::NAME)
+            },
+        }
+    }
+
+    /// Picks the backend to use for `target`. Only screencopy can capture regions of an output, and
+    /// only ext-image-copy-capture can capture windows.
+    pub fn resolve(&self, globals: &[(u32, String, u32)], target: &CaptureTarget) -> CaptureMethod {
+        match (self, target) {
+            (&CaptureMethod::Auto, &CaptureTarget::Toplevel(_)) => CaptureMethod::ImageCopy,
+            (&CaptureMethod::Auto, &CaptureTarget::Output { region: Some(_), .. }) => CaptureMethod::Screencopy,
+            (&CaptureMethod::Auto, &CaptureTarget::Output { region: None, .. }) |
+            (&CaptureMethod::Auto, &CaptureTarget::FocusedOutput(_)) |
+            (&CaptureMethod::Auto, &CaptureTarget::AllOutputs(_)) => {
+                if CaptureMethod::ImageCopy.is_supported(globals) {
+                    CaptureMethod::ImageCopy
+                } else {
+                    CaptureMethod::Screencopy
+                }
+            },
+            (&method, _) => method,
+        }
+    }
+
+    /// Binds the backend for this method, which must not be `Auto`
+    pub(super) fn bind(&self, global_manager: &GlobalManager, target: &CaptureTarget, shm: Attached<WlShm>, sink: FrameSink, buffer_count: usize) -> Result<Box<dyn CaptureBackend>, String> {
+        let combined = match target {
+            &CaptureTarget::AllOutputs(_) => true,
+            _ => false,
+        };
+        let backend: Box<dyn CaptureBackend> = match (self, combined) {
+            (&CaptureMethod::Auto, _) => return Err("Capture method has to be resolved before binding".into()),
+            (&CaptureMethod::Screencopy, false) => Box::new(ScreencopyCapture::bind(global_manager, target, shm, sink, buffer_count)?),
+            (&CaptureMethod::Screencopy, true) => Box::new(CombinedCapture::<ScreencopyCapture>::bind(global_manager, target, shm, sink, buffer_count)?),
+            (&CaptureMethod::ImageCopy, false) => Box::new(ImageCopyCapture::bind(global_manager, target, shm, sink, buffer_count)?),
+            (&CaptureMethod::ImageCopy, true) => Box::new(CombinedCapture::<ImageCopyCapture>::bind(global_manager, target, shm, sink, buffer_count)?),
+        };
+        Ok(backend)
+    }
+}
+
+impl Default for CaptureMethod {
+    fn default() -> Self {
+        CaptureMethod::Auto
+    }
+}
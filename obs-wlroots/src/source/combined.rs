@@ -0,0 +1,486 @@
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicBool};
+use ::obs::sys as obs_sys;
+use wayland_client::{
+    Attached,
+    GlobalManager,
+    Interface,
+    Main
+};
+use wayland_client::protocol::wl_output;
+use wayland_client::protocol::wl_shm::{self, WlShm};
+use wayland_protocols::unstable::xdg_output::v1::client::zxdg_output_manager_v1::ZxdgOutputManagerV1;
+use crate::composite::Canvas;
+use crate::mailbox::Mailbox;
+use crate::mmap::MappedMemory;
+use crate::region::Region;
+use super::backend::{CaptureBackend, CaptureTarget};
+use super::{
+    BufferLease,
+    FrameData,
+    FrameMemory,
+    FrameMetadata,
+    FrameSink,
+    OutputGeometry,
+};
+
+/// One output of a `CombinedCapture`
+struct Part<B> {
+    backend: B,
+    geometry: OutputGeometry,
+    /// Frames of this output, composited by the capture thread instead of going to OBS
+    mailbox: Mailbox<FrameData>,
+    /// Size of the last frame, which tells the output's scale
+    buffer_size: Option<(u32, u32)>,
+    /// Whether a whole frame of this output is on the canvas, so later frames only need their
+    /// damage copied
+    drawn: bool,
+}
+
+impl<B> Part<B> {
+    /// Buffer pixels per logical pixel
+    fn scale(&self) -> Option<f64> {
+        let logical = self.geometry.get()?;
+        let (width, _) = self.buffer_size?;
+        Some(width as f64 / logical.width.max(1) as f64)
+    }
+}
+
+/// Where every output goes on the canvas: the logical bounding box of all outputs, and the canvas
+/// pixels per logical pixel
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Layout {
+    bounds: Region,
+    scale: f64,
+}
+
+impl Layout {
+    /// Layout of outputs at the logical areas in `outputs`, each with its scale. `None` if there
+    /// are no outputs.
+    fn new(outputs: &[(Region, f64)]) -> Option<Layout> {
+        let mut bounds: Option<Region> = None;
+        let mut scale: f64 = 1.0;
+        for &(logical, output_scale) in outputs.iter() {
+            bounds = Some(match bounds {
+                None => logical,
+                Some(b) => {
+                    let x = b.x.min(logical.x);
+                    let y = b.y.min(logical.y);
+                    let right = (b.x + b.width).max(logical.x + logical.width);
+                    let bottom = (b.y + b.height).max(logical.y + logical.height);
+                    Region::new(x, y, right - x, bottom - y)
+                },
+            });
+            scale = output_scale.max(scale);
+        }
+        Some(Layout {
+            bounds: bounds?,
+            scale: scale,
+        })
+    }
+
+    fn canvas_size(&self) -> (u32, u32) {
+        let width = (self.bounds.width as f64 * self.scale).round() as u32;
+        let height = (self.bounds.height as f64 * self.scale).round() as u32;
+        (width, height)
+    }
+
+    /// Canvas area covered by the output at `logical`
+    fn place(&self, logical: &Region) -> Region {
+        let x = ((logical.x - self.bounds.x) as f64 * self.scale).round() as i32;
+        let y = ((logical.y - self.bounds.y) as f64 * self.scale).round() as i32;
+        let right = ((logical.x + logical.width - self.bounds.x) as f64 * self.scale).round() as i32;
+        let bottom = ((logical.y + logical.height - self.bounds.y) as f64 * self.scale).round() as i32;
+        Region::new(x, y, right - x, bottom - y)
+    }
+}
+
+/// Damage rects a `Slot` keeps track of before it's just copied over whole
+const MAX_STALE_RECTS: usize = 64;
+
+/// Memory a composited frame is handed to OBS in, reused once OBS is done with it
+struct Slot {
+    memory: Arc<MappedMemory>,
+    in_use: Arc<AtomicBool>,
+    /// Areas of the canvas that changed since this slot was last written, `None` if all of it
+    stale: Option<Vec<Region>>,
+}
+
+impl Slot {
+    fn new(size: usize) -> Result<Slot, String> {
+        let memory = unsafe {
+            MappedMemory::new(size, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)
+                .map_err(|e| format!("Error mapping frame buffer: {}", e))?
+        };
+        Ok(Slot {
+            memory: Arc::new(memory),
+            in_use: Arc::new(AtomicBool::new(false)),
+            stale: None,
+        })
+    }
+
+    #[inline(always)]
+    fn is_in_use(&self) -> bool {
+        self.in_use.load(atomic::Ordering::Acquire)
+    }
+
+    fn mark_stale(&mut self, changed: &[Region]) {
+        if let Some(stale) = self.stale.as_mut() {
+            stale.extend_from_slice(changed);
+        }
+        if self.stale.as_ref().map(|stale| stale.len() > MAX_STALE_RECTS).unwrap_or(false) {
+            self.stale = None;
+        }
+    }
+
+    /// Brings the slot up to date with `canvas` and leases it out
+    fn fill(&mut self, canvas: &Canvas) -> BufferLease {
+        // Nobody reads the slot while it isn't leased
+        let data = unsafe { self.memory.as_mut_slice() };
+        match self.stale.take() {
+            Some(stale) => {
+                for area in stale.iter() {
+                    canvas.copy_to(data, area);
+                }
+            },
+            None => data.copy_from_slice(canvas.as_slice()),
+        }
+        self.stale = Some(Vec::new());
+        self.in_use.store(true, atomic::Ordering::Release);
+        BufferLease(self.in_use.clone())
+    }
+}
+
+/// Maps `damage` of a `width`x`height` frame to the canvas area the frame is scaled to, `dst`.
+/// Partially covered canvas pixels count as damaged.
+fn place_damage(damage: &Region, width: u32, height: u32, dst: &Region) -> Region {
+    let scale_x = dst.width as f64 / width.max(1) as f64;
+    let scale_y = dst.height as f64 / height.max(1) as f64;
+    let x = (damage.x as f64 * scale_x).floor() as i32;
+    let y = (damage.y as f64 * scale_y).floor() as i32;
+    let right = ((damage.x + damage.width) as f64 * scale_x).ceil() as i32;
+    let bottom = ((damage.y + damage.height) as f64 * scale_y).ceil() as i32;
+    Region::new(dst.x + x, dst.y + y, right - x, bottom - y)
+}
+
+/// Captures every output with `B` and composites them by their logical layout. The canvas is drawn
+/// at the highest output scale, so HiDPI outputs keep their detail and the others are scaled up.
+/// Only the damaged parts of each frame are composited, and OBS gets the canvas in a ring of
+/// buffers that only have the areas changed since their last use copied into them.
+pub(super) struct CombinedCapture<B> {
+    parts: Vec<Part<B>>,
+    output_manager: Main<ZxdgOutputManagerV1>,
+    sink: FrameSink,
+    canvas: Option<(Layout, Canvas)>,
+    slots: Vec<Slot>,
+    slot_count: usize,
+    /// Whether the canvas changed since it was last handed to OBS
+    dirty: bool,
+    /// What changed since then, `None` if unknown
+    unposted: Option<Vec<Region>>,
+}
+
+impl<B: CaptureBackend> CombinedCapture<B> {
+    /// Current layout, once every output's logical geometry is known
+    fn layout(&self) -> Option<Layout> {
+        let outputs: Option<Vec<(Region, f64)>> = self.parts.iter()
+            .map(|part| part.geometry.get().map(|logical| (logical, part.scale().unwrap_or(1.0))))
+            .collect();
+        Layout::new(&outputs?)
+    }
+
+    /// Draws the damaged parts of `frames` onto the canvas
+    fn composite(&mut self, frames: Vec<(usize, FrameData)>) {
+        let layout = match self.layout() {
+            Some(l) => l,
+            None => {
+                for part in self.parts.iter_mut() {
+                    part.drawn = false;
+                }
+                return;
+            },
+        };
+        // A new layout starts out black, everything drawn on the old one is stale
+        if self.canvas.as_ref().map(|&(l, _)| l != layout).unwrap_or(true) {
+            let (width, height) = layout.canvas_size();
+            println!("obs_wlroots: compositing {} outputs into {}x{}", self.parts.len(), width, height);
+            self.canvas = Some((layout, Canvas::new(width, height)));
+            self.slots.clear();
+            self.dirty = true;
+            self.unposted = None;
+            for part in self.parts.iter_mut() {
+                part.drawn = false;
+            }
+        }
+        let canvas = &mut self.canvas.as_mut().unwrap().1;
+        let mut changed = Vec::new();
+        for (i, frame_data) in frames {
+            let part = &mut self.parts[i];
+            let dst = layout.place(&part.geometry.get().unwrap());
+            let FrameData(_, ref frame, ref damage) = frame_data;
+            // Frames of rotated outputs arrive upright already
+            match damage.as_ref().filter(|_| part.drawn) {
+                Some(damage) => {
+                    for rect in damage.iter() {
+                        let area = place_damage(rect, frame.width, frame.height, &dst);
+                        changed.extend(canvas.blit_within(frame, &dst, &area, wl_output::Transform::Normal));
+                    }
+                },
+                None => {
+                    changed.extend(canvas.blit(frame, &dst, wl_output::Transform::Normal));
+                    part.drawn = true;
+                },
+            }
+        }
+        if changed.is_empty() {
+            return;
+        }
+        for slot in self.slots.iter_mut() {
+            slot.mark_stale(&changed);
+        }
+        if let Some(unposted) = self.unposted.as_mut() {
+            unposted.extend(changed);
+        }
+        if self.unposted.as_ref().map(|unposted| unposted.len() > MAX_STALE_RECTS).unwrap_or(false) {
+            self.unposted = None;
+        }
+        self.dirty = true;
+    }
+
+    /// Hands the canvas to OBS, unless every buffer is still in use, in which case the next call
+    /// tries again
+    fn post(&mut self) {
+        let canvas = match self.canvas.as_ref() {
+            Some(&(_, ref canvas)) => canvas,
+            None => return,
+        };
+        let index = match self.slots.iter().position(|slot| !slot.is_in_use()) {
+            Some(i) => i,
+            None if self.slots.len() < self.slot_count => {
+                match Slot::new(canvas.as_slice().len()) {
+                    Ok(slot) => self.slots.push(slot),
+                    Err(e) => {
+                        println!("obs_wlroots: {}", e);
+                        return;
+                    },
+                }
+                self.slots.len() - 1
+            },
+            None => return,
+        };
+        let slot = &mut self.slots[index];
+        let lease = slot.fill(canvas);
+        let damage = mem::replace(&mut self.unposted, Some(Vec::new()));
+        let meta = FrameMetadata::new(wl_shm::Format::Argb8888.to_raw(), canvas.width(), canvas.height(), canvas.stride());
+        let memory = FrameMemory::Mapped(slot.memory.clone(), lease);
+        self.sink.post(memory, &meta, obs_sys::video_format::VIDEO_FORMAT_BGRA, obs::os_gettime_ns(), damage);
+        self.dirty = false;
+    }
+}
+
+impl<B: CaptureBackend> CaptureBackend for CombinedCapture<B> {
+    fn bind(global_manager: &GlobalManager, target: &CaptureTarget, shm: Attached<WlShm>, sink: FrameSink, buffer_count: usize) -> Result<CombinedCapture<B>, String> {
+        let outputs = match target {
+            &CaptureTarget::AllOutputs(ref outputs) => outputs,
+            _ => return Err("Only all outputs can be combined".into()),
+        };
+        let output_manager = global_manager.instantiate_exact::<ZxdgOutputManagerV1>(2)
+            .map_err(|e| format!("Error instantiating {}: {}", <ZxdgOutputManagerV1 as Interface>::NAME, e))?;
+        let mut parts = Vec::with_capacity(outputs.len());
+        for output in outputs.iter() {
+            let mailbox = Mailbox::new();
+            let part_sink = FrameSink {
+                mailbox: Some(mailbox.clone()),
+                source_handle: sink.source_handle,
+            };
+            let output_target = CaptureTarget::Output {
+                output: output.clone(),
+                region: None,
+            };
+            let backend = match B::bind(global_manager, &output_target, shm.clone(), part_sink, buffer_count) {
+                Ok(b) => b,
+                Err(e) => {
+                    mem::drop(parts);
+                    output_manager.destroy();
+                    return Err(e);
+                },
+            };
+            parts.push(Part {
+                backend: backend,
+                geometry: OutputGeometry::new(&output_manager, &output.handle),
+                mailbox: mailbox,
+                buffer_size: None,
+                drawn: false,
+            });
+        }
+        Ok(CombinedCapture {
+            parts: parts,
+            output_manager: output_manager,
+            sink: sink,
+            canvas: None,
+            slots: Vec::new(),
+            slot_count: buffer_count.max(2),
+            dirty: false,
+            unposted: None,
+        })
+    }
+
+    fn request_frame(&mut self, overlay_cursor: bool) -> bool {
+        let mut requested = false;
+        for part in self.parts.iter_mut() {
+            requested = part.backend.request_frame(overlay_cursor) || requested;
+        }
+
+        let mut frames = Vec::new();
+        for (i, part) in self.parts.iter_mut().enumerate() {
+            if let Some(frame) = part.mailbox.take() {
+                part.buffer_size = Some((frame.1.width, frame.1.height));
+                frames.push((i, frame));
+            }
+        }
+        if !frames.is_empty() {
+            self.composite(frames);
+        }
+        if self.dirty {
+            self.post();
+        }
+        requested
+    }
+
+    fn is_waiting(&self) -> bool {
+        self.parts.iter().any(|part| part.backend.is_waiting())
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.parts.iter().all(|part| part.backend.is_stopped())
+    }
+
+    fn has_target(&self) -> bool {
+        self.parts.iter().any(|part| part.backend.has_target())
+    }
+
+    fn cancel(&mut self) {
+        for part in self.parts.iter_mut() {
+            part.backend.cancel();
+        }
+    }
+}
+
+impl<B> Drop for CombinedCapture<B> {
+    fn drop(&mut self) {
+        // xdg_outputs go before their manager
+        self.parts.clear();
+        self.output_manager.destroy();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn damage_is_scaled_to_the_placed_area() {
+        let dst = Region::new(100, 50, 200, 100);
+        assert_eq!(place_damage(&Region::new(10, 10, 20, 20), 400, 200, &dst), Region::new(105, 55, 10, 10));
+        // Partially covered pixels are included
+        assert_eq!(place_damage(&Region::new(1, 1, 1, 1), 400, 200, &dst), Region::new(100, 50, 1, 1));
+    }
+
+    /// BGRX frame of `width`x`height` pixels that all have blue value `blue`
+    fn solid_frame(buffer: &mut Vec<u8>, width: u32, height: u32, blue: u8) -> obs_sys::obs_source_frame {
+        *buffer = vec![0u8; (width * height * 4) as usize];
+        for px in buffer.chunks_mut(4) {
+            px[0] = blue;
+        }
+        let mut frame: obs_sys::obs_source_frame = unsafe { mem::zeroed() };
+        frame.data[0] = buffer.as_mut_ptr();
+        frame.linesize[0] = width * 4;
+        frame.width = width;
+        frame.height = height;
+        frame.format = obs_sys::video_format::VIDEO_FORMAT_BGRX;
+        frame
+    }
+
+    fn contents(slot: &Slot) -> Vec<u8> {
+        unsafe { slot.memory.as_mut_slice() }.to_vec()
+    }
+
+    #[test]
+    fn layout_of_mixed_scales_and_negative_positions() {
+        // A scale 2 4K output above and to the right of a scale 1 1080p one left of the origin
+        let left = Region::new(-1920, 0, 1920, 1080);
+        let right = Region::new(0, -500, 1920, 1080);
+        let layout = Layout::new(&[(left, 1.0), (right, 2.0)]).unwrap();
+        assert_eq!(layout, Layout {
+            bounds: Region::new(-1920, -500, 3840, 1580),
+            scale: 2.0,
+        });
+        assert_eq!(layout.canvas_size(), (7680, 3160));
+        assert_eq!(layout.place(&left), Region::new(0, 1000, 3840, 2160));
+        assert_eq!(layout.place(&right), Region::new(3840, 0, 3840, 2160));
+
+        // Fractional scales round to whole canvas pixels without gaps between outputs
+        let layout = Layout::new(&[(Region::new(-1000, 0, 1000, 600), 1.5), (Region::new(0, 0, 1280, 720), 1.0)]).unwrap();
+        assert_eq!(layout.canvas_size(), (3420, 1080));
+        assert_eq!(layout.place(&Region::new(-1000, 0, 1000, 600)), Region::new(0, 0, 1500, 900));
+        assert_eq!(layout.place(&Region::new(0, 0, 1280, 720)), Region::new(1500, 0, 1920, 1080));
+
+        assert_eq!(Layout::new(&[]), None);
+    }
+
+    #[test]
+    fn stale_slot_is_refilled_after_reuse_while_leased() {
+        let mut buffer = Vec::new();
+        let mut canvas = Canvas::new(4, 2);
+        let size = canvas.as_slice().len();
+        let mut first = Slot::new(size).unwrap();
+        let mut second = Slot::new(size).unwrap();
+
+        let first_lease = first.fill(&canvas);
+        assert!(first.is_in_use());
+        assert_eq!(contents(&first), canvas.as_slice());
+
+        // Drawn while the first slot is leased, so the next frame goes to the second
+        let frame = solid_frame(&mut buffer, 1, 1, 10);
+        let changed = canvas.blit(&frame, &Region::new(0, 0, 2, 2), wl_output::Transform::Normal).unwrap();
+        first.mark_stale(&[changed]);
+        second.mark_stale(&[changed]);
+        let second_lease = second.fill(&canvas);
+        assert_eq!(contents(&second), canvas.as_slice());
+
+        // Drawn while both are leased
+        let frame = solid_frame(&mut buffer, 1, 1, 20);
+        let changed = canvas.blit(&frame, &Region::new(3, 1, 1, 1), wl_output::Transform::Normal).unwrap();
+        first.mark_stale(&[changed]);
+        second.mark_stale(&[changed]);
+
+        // The first slot catches up on both changes once it's free again, and only those areas
+        // are copied
+        mem::drop(first_lease);
+        assert!(!first.is_in_use());
+        unsafe { first.memory.as_mut_slice()[8] = 0xee };
+        let _first_lease = first.fill(&canvas);
+        let mut expected = canvas.as_slice().to_vec();
+        // Pixel (2, 0) never changed
+        expected[8] = 0xee;
+        assert_eq!(contents(&first), expected);
+
+        mem::drop(second_lease);
+        second.fill(&canvas);
+        assert_eq!(contents(&second), canvas.as_slice());
+    }
+
+    #[test]
+    fn slot_with_too_much_damage_is_copied_whole() {
+        let mut canvas = Canvas::new(4, 2);
+        let mut slot = Slot::new(canvas.as_slice().len()).unwrap();
+        mem::drop(slot.fill(&canvas));
+        canvas.fill([1, 2, 3, 0xff]);
+        let pixel = Region::new(0, 0, 1, 1);
+        slot.mark_stale(&vec![pixel; MAX_STALE_RECTS + 1]);
+        assert!(slot.stale.is_none());
+        slot.fill(&canvas);
+        assert_eq!(contents(&slot), canvas.as_slice());
+    }
+}
@@ -0,0 +1,411 @@
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::rc::Rc;
+use std::sync::Arc;
+use wayland_client::{
+    Attached,
+    GlobalManager,
+    Interface,
+    Main
+};
+use wayland_client::protocol::wl_shm::{self, WlShm};
+use crate::protocols::ext_image_capture_source::client::ext_image_capture_source_v1::ExtImageCaptureSourceV1;
+use crate::protocols::ext_image_capture_source::client::ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1;
+use crate::protocols::ext_image_capture_source::client::ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1;
+use crate::protocols::ext_image_copy_capture::client::ext_image_copy_capture_manager_v1::{self, ExtImageCopyCaptureManagerV1};
+use crate::protocols::ext_image_copy_capture::client::ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1};
+use crate::protocols::ext_image_copy_capture::client::ext_image_copy_capture_frame_v1::{self, ExtImageCopyCaptureFrameV1};
+use crate::format::ShmFormat;
+use crate::mmap::MappedMemory;
+use crate::region::Region;
+use crate::toplevel::{ExtToplevels, Window, WindowRules};
+use super::backend::{CaptureBackend, CaptureTarget};
+use super::{
+    BufferLease,
+    BufferRing,
+    FrameMetadata,
+    FrameSink,
+    presentation_time_ns,
+};
+
+/// Finds the window to capture in the toplevel list
+struct ToplevelSource {
+    rules: WindowRules,
+    toplevels: ExtToplevels,
+    manager: Main<ExtForeignToplevelImageCaptureSourceManagerV1>,
+    /// Serial of the toplevel list the rules were last evaluated against
+    serial: Option<u64>,
+    matched: Option<Window>,
+}
+
+impl ToplevelSource {
+    fn new(rules: WindowRules, toplevels: ExtToplevels, manager: Main<ExtForeignToplevelImageCaptureSourceManagerV1>) -> ToplevelSource {
+        ToplevelSource {
+            rules: rules,
+            toplevels: toplevels,
+            manager: manager,
+            serial: None,
+            matched: None,
+        }
+    }
+
+    /// Re-evaluates the rules if the toplevel list changed since the last call. Returns the newly
+    /// matched window if the match changed.
+    fn rematch(&mut self) -> Option<Option<Window>> {
+        let serial = self.toplevels.serial();
+        if self.serial == Some(serial) {
+            return None;
+        }
+        self.serial = Some(serial);
+        let matched = self.rules.best_match(&self.toplevels.windows()).cloned();
+        if matched == self.matched {
+            return None;
+        }
+        match matched.as_ref() {
+            Some(window) => println!("obs_wlroots: matched window {}", window.label()),
+            None => println!("obs_wlroots: no window matches"),
+        }
+        self.matched = matched.clone();
+        Some(matched)
+    }
+
+    /// Forgets the current match, so the next `rematch` starts over
+    fn reset(&mut self) {
+        self.serial = None;
+        self.matched = None;
+    }
+
+    fn create_source(&self, window: &Window) -> Option<Main<ExtImageCaptureSourceV1>> {
+        let handle = self.toplevels.find(window)?;
+        Some(self.manager.create_source(&handle))
+    }
+}
+
+impl Drop for ToplevelSource {
+    fn drop(&mut self) {
+        self.manager.destroy();
+    }
+}
+
+/// Buffer constraints the session has announced since the last `done`
+#[derive(Default)]
+struct Constraints {
+    width: u32,
+    height: u32,
+    formats: Vec<wl_shm::Format>,
+}
+
+impl Constraints {
+    /// Picks the buffer layout to allocate, preferring formats OBS can take without conversion
+    fn choose_layout(&self) -> Option<FrameMetadata> {
+        let supported = || self.formats.iter()
+            .filter_map(|&f| ShmFormat::from_wl_shm(f).map(|shm_format| (f, shm_format)));
+        let (format, _) = supported()
+            .find(|&(_, shm_format)| !shm_format.needs_conversion())
+            .or_else(|| supported().next())?;
+        Some(FrameMetadata::new(format.to_raw(), self.width, self.height, self.width * 4))
+    }
+}
+
+/// State shared between the capture and its session and frame event handlers
+struct SessionState {
+    pending: RefCell<Constraints>,
+    /// Buffer layout frames are captured into, `None` until the session's constraints are known
+    layout: Cell<Option<FrameMetadata>>,
+    buffers: RefCell<BufferRing>,
+    sink: FrameSink,
+    waiting: Cell<bool>,
+    stopped: Cell<bool>,
+    /// Set when capturing can't go on, e.g. because no buffer could be allocated
+    failed: Cell<bool>,
+    /// Mapping and lease of the buffer the in-flight frame is copied into
+    current: RefCell<Option<(Arc<MappedMemory>, BufferLease)>>,
+    /// The in-flight frame, kept so it can be abandoned
+    frame: RefCell<Option<ExtImageCopyCaptureFrameV1>>,
+    /// Layout and transform of the in-flight frame
+    metadata: Cell<FrameMetadata>,
+    damage: RefCell<Vec<Region>>,
+    timestamp: Cell<Option<u64>>,
+}
+
+impl SessionState {
+    fn handle_session_event(&self, event: ext_image_copy_capture_session_v1::Event) {
+        use ext_image_copy_capture_session_v1::Event;
+        match event {
+            Event::BufferSize { width, height } => {
+                let mut pending = self.pending.borrow_mut();
+                pending.width = width;
+                pending.height = height;
+            },
+            Event::ShmFormat { format } => {
+                self.pending.borrow_mut().formats.push(format);
+            },
+            Event::Done => {
+                let mut pending = self.pending.borrow_mut();
+                let layout = pending.choose_layout();
+                if layout.is_none() {
+                    println!("obs_wlroots: no supported wl_shm format among {:?}, stopping capture", pending.formats);
+                    self.failed.set(true);
+                }
+                self.layout.set(layout);
+                pending.formats.clear();
+            },
+            Event::Stopped => {
+                self.stopped.set(true);
+            },
+            _ => {},
+        }
+    }
+
+    fn handle_frame_event(&self, frame: &ExtImageCopyCaptureFrameV1, event: ext_image_copy_capture_frame_v1::Event) {
+        use ext_image_copy_capture_frame_v1::{Event, FailureReason};
+        match event {
+            Event::Transform { transform } => {
+                let mut meta = self.metadata.get();
+                meta.transform = transform;
+                self.metadata.set(meta);
+            },
+            Event::Damage { x, y, width, height } => {
+                self.damage.borrow_mut().push(Region::new(x, y, width, height));
+            },
+            Event::PresentationTime { tv_sec_hi, tv_sec_lo, tv_nsec } => {
+                self.timestamp.set(Some(presentation_time_ns(tv_sec_hi, tv_sec_lo, tv_nsec)));
+            },
+            Event::Ready => {
+                let (buf, lease) = match self.current.borrow_mut().take() {
+                    Some(current) => current,
+                    None => {
+                        println!("obs_wlroots: compositor finished a frame that wasn't copied into a buffer");
+                        self.failed.set(true);
+                        self.finish(frame);
+                        return;
+                    },
+                };
+                let damage = mem::replace(&mut *self.damage.borrow_mut(), Vec::new());
+                let timestamp = self.timestamp.get()
+                    .unwrap_or_else(obs::os_gettime_ns);
+                self.sink.deliver(buf, lease, &self.metadata.get(), timestamp, Some(damage));
+                self.finish(frame);
+            },
+            Event::Failed { reason } => {
+                mem::drop(self.current.borrow_mut().take());
+                match reason {
+                    // New constraints are on their way, wait for them before the next frame
+                    FailureReason::BufferConstraints => self.layout.set(None),
+                    FailureReason::Stopped => self.stopped.set(true),
+                    _ => {},
+                }
+                self.finish(frame);
+            },
+            _ => {},
+        }
+    }
+
+    /// Destroys the in-flight `frame`, making way for the next one
+    fn finish(&self, frame: &ExtImageCopyCaptureFrameV1) {
+        mem::drop(self.frame.borrow_mut().take());
+        self.waiting.set(false);
+        frame.destroy();
+    }
+
+    /// Abandons the in-flight frame, if any. The compositor may hold on to it until the source
+    /// changes, which may be never.
+    fn cancel(&self) {
+        if let Some(frame) = self.frame.borrow_mut().take() {
+            frame.destroy();
+        }
+        mem::drop(self.current.borrow_mut().take());
+        self.waiting.set(false);
+    }
+}
+
+/// Captures an image source through an ext-image-copy-capture session, one frame at a time
+pub(super) struct ImageCopyCapture {
+    copy_manager: Main<ExtImageCopyCaptureManagerV1>,
+    /// `None` until the window to capture has been found
+    source: Option<Main<ExtImageCaptureSourceV1>>,
+    toplevel: Option<ToplevelSource>,
+    /// The current session, and whether it paints cursors
+    session: Option<(Main<ExtImageCopyCaptureSessionV1>, bool)>,
+    state: Rc<SessionState>,
+}
+
+impl ImageCopyCapture {
+    fn new(copy_manager: Main<ExtImageCopyCaptureManagerV1>, source: Option<Main<ExtImageCaptureSourceV1>>, toplevel: Option<ToplevelSource>, shm: Attached<WlShm>, sink: FrameSink, buffer_count: usize) -> ImageCopyCapture {
+        let state = SessionState {
+            pending: RefCell::new(Constraints::default()),
+            layout: Cell::new(None),
+            buffers: RefCell::new(BufferRing::new(shm, buffer_count)),
+            sink: sink,
+            waiting: Cell::new(false),
+            stopped: Cell::new(false),
+            failed: Cell::new(false),
+            current: RefCell::new(None),
+            frame: RefCell::new(None),
+            metadata: Cell::new(FrameMetadata::default()),
+            damage: RefCell::new(Vec::new()),
+            timestamp: Cell::new(None),
+        };
+        ImageCopyCapture {
+            copy_manager: copy_manager,
+            source: source,
+            toplevel: toplevel,
+            session: None,
+            state: Rc::new(state),
+        }
+    }
+
+    /// Ends the current session and destroys its source
+    fn end_session(&mut self) {
+        self.state.cancel();
+        if let Some((session, _)) = self.session.take() {
+            session.destroy();
+        }
+        if let Some(source) = self.source.take() {
+            source.destroy();
+        }
+        self.state.layout.set(None);
+        self.state.stopped.set(false);
+    }
+
+    /// Replaces the current session, if any. Cursor painting is fixed for the lifetime of a
+    /// session, so toggling it means starting over.
+    fn start_session(&mut self, paint_cursors: bool) {
+        if let Some((session, _)) = self.session.take() {
+            session.destroy();
+        }
+        self.state.layout.set(None);
+        *self.state.pending.borrow_mut() = Constraints::default();
+        self.state.stopped.set(false);
+
+        let options = if paint_cursors {
+            ext_image_copy_capture_manager_v1::Options::PaintCursors
+        } else {
+            ext_image_copy_capture_manager_v1::Options::empty()
+        };
+        let session = self.copy_manager.create_session(self.source.as_ref().unwrap(), options);
+        let state = self.state.clone();
+        session.assign_mono(move |_, evt| state.handle_session_event(evt));
+        self.session = Some((session, paint_cursors));
+    }
+
+}
+
+impl CaptureBackend for ImageCopyCapture {
+    fn bind(global_manager: &GlobalManager, target: &CaptureTarget, shm: Attached<WlShm>, sink: FrameSink, buffer_count: usize) -> Result<ImageCopyCapture, String> {
+        let (source, toplevel) = match target {
+            &CaptureTarget::Output { region: Some(_), .. } => {
+                return Err("ext-image-copy-capture can't capture regions".into());
+            },
+            &CaptureTarget::Output { ref output, region: None } => {
+                // Frames report their own transform
+                let manager = global_manager.instantiate_exact::<ExtOutputImageCaptureSourceManagerV1>(1)
+                    .map_err(|e| format!("Error instantiating {}: {}", <ExtOutputImageCaptureSourceManagerV1 as Interface>::NAME, e))?;
+                let source = manager.create_source(&output.handle);
+                manager.destroy();
+                (Some(source), None)
+            },
+            &CaptureTarget::FocusedOutput(_) => {
+                return Err("The focused output has to be resolved before capturing it".into());
+            },
+            &CaptureTarget::AllOutputs(_) => {
+                return Err("Outputs have to be captured one by one to be combined".into());
+            },
+            &CaptureTarget::Toplevel(ref rules) => {
+                let toplevels = ExtToplevels::new(global_manager)
+                    .ok_or_else(|| String::from("Compositor doesn't list windows through ext-foreign-toplevel-list"))?;
+                let manager = global_manager.instantiate_exact::<ExtForeignToplevelImageCaptureSourceManagerV1>(1)
+                    .map_err(|e| format!("Error instantiating {}: {}", <ExtForeignToplevelImageCaptureSourceManagerV1 as Interface>::NAME, e))?;
+                (None, Some(ToplevelSource::new(rules.clone(), toplevels, manager)))
+            },
+        };
+        let copy_manager = global_manager.instantiate_exact::<ExtImageCopyCaptureManagerV1>(1)
+            .map_err(|e| format!("Error instantiating {}: {}", <ExtImageCopyCaptureManagerV1 as Interface>::NAME, e))?;
+        Ok(ImageCopyCapture::new(copy_manager, source, toplevel, shm, sink, buffer_count))
+    }
+
+    fn request_frame(&mut self, overlay_cursor: bool) -> bool {
+        if self.state.waiting.get() {
+            return false;
+        }
+        if self.state.stopped.get() {
+            match self.toplevel.as_mut() {
+                // The window went away, or something else ended its session. Look for it again.
+                Some(toplevel) => toplevel.reset(),
+                None => return false,
+            }
+            self.end_session();
+        }
+        if let Some(matched) = self.toplevel.as_mut().and_then(ToplevelSource::rematch) {
+            self.end_session();
+            let toplevel = self.toplevel.as_ref().unwrap();
+            self.source = matched.and_then(|window| toplevel.create_source(&window));
+        }
+        if self.source.is_none() {
+            return false;
+        }
+        if self.session.as_ref().map(|&(_, cursors)| cursors) != Some(overlay_cursor) {
+            self.start_session(overlay_cursor);
+        }
+        let layout = match self.state.layout.get() {
+            Some(l) => l,
+            None => return false,
+        };
+        let mut buffers = self.state.buffers.borrow_mut();
+        let (buffer, lease) = match buffers.acquire(&layout) {
+            Ok(Some(b)) => b,
+            // Every buffer is still being consumed, try again once one comes back
+            Ok(None) => return false,
+            Err(e) => {
+                println!("obs_wlroots: {}", e);
+                self.state.failed.set(true);
+                return false;
+            },
+        };
+        *self.state.current.borrow_mut() = Some((buffer.mapping(), lease));
+        self.state.metadata.set(layout);
+        self.state.damage.borrow_mut().clear();
+        self.state.timestamp.set(None);
+
+        let &(ref session, _) = self.session.as_ref().unwrap();
+        let frame = session.create_frame();
+        let state = self.state.clone();
+        frame.assign_mono(move |obj, evt| state.handle_frame_event(&obj, evt));
+        frame.attach_buffer(&buffer.buffer);
+        // Ring buffers hold older frames, so the whole buffer has to be rewritten
+        frame.damage_buffer(0, 0, layout.width as i32, layout.height as i32);
+        frame.capture();
+        *self.state.frame.borrow_mut() = Some((**frame).clone());
+        self.state.waiting.set(true);
+        true
+    }
+
+    #[inline(always)]
+    fn is_waiting(&self) -> bool {
+        self.state.waiting.get()
+    }
+
+    #[inline(always)]
+    fn is_stopped(&self) -> bool {
+        // Windows are looked for again when they go away
+        self.state.failed.get() || (self.toplevel.is_none() && self.state.stopped.get())
+    }
+
+    #[inline(always)]
+    fn cancel(&mut self) {
+        self.state.cancel();
+    }
+
+    #[inline(always)]
+    fn has_target(&self) -> bool {
+        self.source.is_some()
+    }
+}
+
+impl Drop for ImageCopyCapture {
+    fn drop(&mut self) {
+        println!("obs_wlroots: ImageCopyCapture::drop");
+        self.end_session();
+        self.copy_manager.destroy();
+    }
+}
@@ -0,0 +1,127 @@
+use std::mem;
+use ::obs::sys as obs_sys;
+use wayland_client::protocol::wl_output::Transform;
+use crate::composite::Canvas;
+use crate::region::Region;
+
+/// What the source shows while nothing can be captured
+#[derive(Clone, Debug, PartialEq)]
+pub enum Placeholder {
+    /// A solid color, as OBS stores colors: `0xAABBGGRR`
+    Color(u32),
+    /// An image file, fitted into the placeholder and centered on black
+    Image(String),
+    /// Whatever was captured last
+    LastFrame,
+}
+
+impl Placeholder {
+    /// Values of the `placeholder` setting, with their descriptions
+    pub const KINDS: &'static [(&'static str, &'static str)] = &[
+        ("color", "Solid color"),
+        ("image", "Image"),
+        ("last_frame", "Last captured frame"),
+    ];
+
+    pub fn from_settings(settings: &obs_sys::obs_data_t) -> Placeholder {
+        use obs::data::ObsData;
+
+        let kind = settings.get_str("placeholder")
+            .map(|s| s.into_owned())
+            .unwrap_or_default();
+        match kind.as_str() {
+            "image" => {
+                let path = settings.get_str("placeholder_image")
+                    .map(|s| s.into_owned())
+                    .unwrap_or_default();
+                Placeholder::Image(path)
+            },
+            "last_frame" => Placeholder::LastFrame,
+            _ => Placeholder::Color(settings.get_int("placeholder_color") as u32),
+        }
+    }
+
+    /// Draws the placeholder at `width`x`height`, `None` for `LastFrame`, which has nothing to draw.
+    /// `image` is the decoded `Image`, if it could be loaded.
+    pub fn draw(&self, width: u32, height: u32, image: &PlaceholderImage) -> Option<Canvas> {
+        let mut canvas = Canvas::new(width, height);
+        match self {
+            &Placeholder::Color(color) => {
+                let [r, g, b, a] = color.to_le_bytes();
+                canvas.fill([b, g, r, a]);
+            },
+            &Placeholder::Image(_) => {
+                if let Some(image) = image.image.as_ref() {
+                    draw_image(&mut canvas, image);
+                }
+            },
+            &Placeholder::LastFrame => return None,
+        }
+        Some(canvas)
+    }
+}
+
+/// The image of an `Image` placeholder, decoded when the settings change rather than every time the
+/// placeholder is drawn
+#[derive(Default)]
+pub struct PlaceholderImage {
+    path: Option<String>,
+    image: Option<obs::gs::Image>,
+}
+
+impl PlaceholderImage {
+    /// Loads the image `placeholder` shows, unless it's already loaded. A missing image is looked
+    /// for again on the next change of settings.
+    pub fn update(&mut self, placeholder: &Placeholder) {
+        let path = match placeholder {
+            &Placeholder::Image(ref path) => Some(path),
+            _ => None,
+        };
+        if path == self.path.as_ref() && (path.is_none() || self.image.is_some()) {
+            return;
+        }
+        self.path = path.cloned();
+        self.image = path.and_then(|path| {
+            let image = obs::gs::Image::load(path);
+            if image.is_none() {
+                println!("obs_wlroots: error loading placeholder image \"{}\"", path);
+            }
+            image
+        });
+    }
+}
+
+/// Scales `image` to fit `canvas`, keeping its aspect ratio
+fn draw_image(canvas: &mut Canvas, image: &obs::gs::Image) {
+    use obs_sys::gs_color_format::*;
+    use obs_sys::video_format::*;
+
+    let format = match image.color_format() {
+        GS_BGRA => VIDEO_FORMAT_BGRA,
+        GS_BGRX => VIDEO_FORMAT_BGRX,
+        GS_RGBA => VIDEO_FORMAT_RGBA,
+        format => {
+            println!("obs_wlroots: unsupported placeholder image format: {:?}", format);
+            return;
+        },
+    };
+    let mut frame: obs_sys::obs_source_frame = unsafe { mem::zeroed() };
+    frame.data[0] = image.data().as_ptr() as *mut u8;
+    frame.linesize[0] = image.width() * 4;
+    frame.width = image.width();
+    frame.height = image.height();
+    frame.format = format;
+
+    let scale = (canvas.width() as f64 / image.width() as f64).min(canvas.height() as f64 / image.height() as f64);
+    let width = (image.width() as f64 * scale).round() as i32;
+    let height = (image.height() as f64 * scale).round() as i32;
+    let x = (canvas.width() as i32 - width) / 2;
+    let y = (canvas.height() as i32 - height) / 2;
+    canvas.blit(&frame, &Region::new(x, y, width, height), Transform::Normal);
+}
+
+impl Default for Placeholder {
+    fn default() -> Self {
+        Placeholder::Color(0xff000000)
+    }
+}
@@ -0,0 +1,510 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::{Rc, Weak};
+use std::str::FromStr;
+use regex::Regex;
+use wayland_client::{GlobalManager, Main};
+use wayland_client::protocol::wl_output::WlOutput;
+use wayland_protocols::wlr::unstable::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1};
+use wayland_protocols::wlr::unstable::foreign_toplevel::v1::client::zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1};
+use crate::protocols::ext_foreign_toplevel_list::client::ext_foreign_toplevel_list_v1::{self, ExtForeignToplevelListV1};
+use crate::protocols::ext_foreign_toplevel_list::client::ext_foreign_toplevel_handle_v1::{self, ExtForeignToplevelHandleV1};
+
+/// A window, as far as the compositor tells us about it
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Window {
+    pub app_id: String,
+    pub title: String,
+}
+
+impl Window {
+    pub fn new<A: Into<String>, T: Into<String>>(app_id: A, title: T) -> Window {
+        Window {
+            app_id: app_id.into(),
+            title: title.into(),
+        }
+    }
+
+    /// Name to show in the properties
+    pub fn label(&self) -> String {
+        match (self.title.len(), self.app_id.len()) {
+            (0, _) => self.app_id.clone(),
+            (_, 0) => self.title.clone(),
+            _ => format!("{} ({})", self.title, self.app_id),
+        }
+    }
+}
+
+/// Formats as `"app_id:title"`, the form stored in the source settings
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.app_id, self.title)
+    }
+}
+
+/// Parses `"app_id:title"`. App ids don't contain colons, titles may.
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Window, String> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(app_id), Some(title)) => Ok(Window::new(app_id, title)),
+            _ => Err(format!("Invalid window \"{}\", expected \"app_id:title\"", s)),
+        }
+    }
+}
+
+/// Matches one property of a window
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    Exact(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn matches(&self, s: &str) -> bool {
+        match self {
+            &Pattern::Exact(ref exact) => exact == s,
+            &Pattern::Regex(ref regex) => regex.is_match(s),
+        }
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        match (self, other) {
+            (&Pattern::Exact(ref a), &Pattern::Exact(ref b)) => a == b,
+            (&Pattern::Regex(ref a), &Pattern::Regex(ref b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+/// A window matches a rule if it matches every pattern the rule has
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WindowRule {
+    pub app_id: Option<Pattern>,
+    pub title: Option<Pattern>,
+}
+
+impl WindowRule {
+    /// Rule from separately configured `app_id` and `title` patterns, matched exactly or, with
+    /// `regex`, as regular expressions. Empty patterns match anything, and `None` is returned if
+    /// both are empty.
+    pub fn new(app_id: &str, title: &str, regex: bool) -> Result<Option<WindowRule>, String> {
+        let pattern = |s: &str| -> Result<Option<Pattern>, String> {
+            if s.len() == 0 {
+                Ok(None)
+            } else if regex {
+                Regex::new(s)
+                    .map(|r| Some(Pattern::Regex(r)))
+                    .map_err(|e| format!("Invalid regex \"{}\": {}", s, e))
+            } else {
+                Ok(Some(Pattern::Exact(s.into())))
+            }
+        };
+        let ret = WindowRule {
+            app_id: pattern(app_id)?,
+            title: pattern(title)?,
+        };
+        if ret.app_id.is_none() && ret.title.is_none() {
+            Ok(None)
+        } else {
+            Ok(Some(ret))
+        }
+    }
+
+    /// Rule matching exactly `window`, i.e. the window picked from the list in the properties
+    pub fn exact(window: &Window) -> WindowRule {
+        WindowRule {
+            app_id: Some(Pattern::Exact(window.app_id.clone())),
+            title: Some(Pattern::Exact(window.title.clone())),
+        }
+    }
+
+    pub fn matches(&self, window: &Window) -> bool {
+        self.app_id.as_ref().map(|p| p.matches(&window.app_id)).unwrap_or(true)
+            && self.title.as_ref().map(|p| p.matches(&window.title)).unwrap_or(true)
+    }
+}
+
+/// Window rules in priority order
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WindowRules(Vec<WindowRule>);
+
+impl WindowRules {
+    #[inline(always)]
+    pub fn push(&mut self, rule: WindowRule) {
+        self.0.push(rule);
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The window matched by the first rule that matches any of `windows`. Among windows matching
+    /// the same rule the first one wins, so `windows` should be in a stable order.
+    pub fn best_match<'a>(&self, windows: &'a [Window]) -> Option<&'a Window> {
+        self.0.iter()
+            .filter_map(|rule| windows.iter().find(|w| rule.matches(w)))
+            .next()
+    }
+}
+
+/// A toplevel handle, and its state as of the last `done`
+struct Toplevel<H> {
+    handle: H,
+    pending: Window,
+    current: Option<Window>,
+    /// Focus and outputs are only reported by wlr-foreign-toplevel-management
+    pending_activated: bool,
+    activated: bool,
+    outputs: Vec<WlOutput>,
+}
+
+impl<H> Toplevel<H> {
+    fn new(handle: H) -> Toplevel<H> {
+        Toplevel {
+            handle: handle,
+            pending: Window::default(),
+            current: None,
+            pending_activated: false,
+            activated: false,
+            outputs: Vec::new(),
+        }
+    }
+}
+
+/// Windows reported through wlr-foreign-toplevel-management
+pub struct WlrToplevels {
+    manager: Main<ZwlrForeignToplevelManagerV1>,
+    toplevels: Rc<RefCell<Vec<Toplevel<Main<ZwlrForeignToplevelHandleV1>>>>>,
+}
+
+impl WlrToplevels {
+    /// Starts tracking windows, or returns `None` if the compositor doesn't support it. Windows
+    /// show up once the queue of `global_manager` has been dispatched.
+    pub fn new(global_manager: &GlobalManager) -> Option<WlrToplevels> {
+        let manager = global_manager.instantiate_range::<ZwlrForeignToplevelManagerV1>(1, 2).ok()?;
+        let toplevels = Rc::new(RefCell::new(Vec::new()));
+        let handler_toplevels = Rc::downgrade(&toplevels);
+        manager.assign_mono(move |_, evt| {
+            match evt {
+                zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } => {
+                    let toplevels = handler_toplevels.clone();
+                    toplevel.assign_mono(move |handle, evt| WlrToplevels::handle_event(&toplevels, &handle, evt));
+                    if let Some(toplevels) = handler_toplevels.upgrade() {
+                        toplevels.borrow_mut().push(Toplevel::new(toplevel));
+                    }
+                },
+                _ => {},
+            }
+        });
+        Some(WlrToplevels {
+            manager: manager,
+            toplevels: toplevels,
+        })
+    }
+
+    fn handle_event(toplevels: &Weak<RefCell<Vec<Toplevel<Main<ZwlrForeignToplevelHandleV1>>>>>, handle: &ZwlrForeignToplevelHandleV1, event: zwlr_foreign_toplevel_handle_v1::Event) {
+        use zwlr_foreign_toplevel_handle_v1::Event;
+        let toplevels = match toplevels.upgrade() {
+            Some(t) => t,
+            None => return,
+        };
+        let mut toplevels = toplevels.borrow_mut();
+        let index = match toplevels.iter().position(|t| t.handle.as_ref().equals(handle.as_ref())) {
+            Some(i) => i,
+            None => return,
+        };
+        match event {
+            Event::Title { title } => toplevels[index].pending.title = title,
+            Event::AppId { app_id } => toplevels[index].pending.app_id = app_id,
+            Event::State { state } => {
+                // Array of native-endian u32 state values
+                let activated = zwlr_foreign_toplevel_handle_v1::State::Activated.to_raw();
+                toplevels[index].pending_activated = state.chunks(4)
+                    .filter(|c| c.len() == 4)
+                    .any(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]) == activated);
+            },
+            Event::OutputEnter { output } => {
+                let outputs = &mut toplevels[index].outputs;
+                if !outputs.iter().any(|o| o.as_ref().equals(output.as_ref())) {
+                    outputs.push(output);
+                }
+            },
+            Event::OutputLeave { output } => {
+                toplevels[index].outputs.retain(|o| !o.as_ref().equals(output.as_ref()));
+            },
+            Event::Done => {
+                let toplevel = &mut toplevels[index];
+                toplevel.current = Some(toplevel.pending.clone());
+                toplevel.activated = toplevel.pending_activated;
+            },
+            Event::Closed => {
+                toplevels.remove(index).handle.destroy();
+            },
+            _ => {},
+        }
+    }
+
+    /// Output the focused window is on. A window spanning several outputs counts as being on the
+    /// one it entered first.
+    pub fn focused_output(&self) -> Option<WlOutput> {
+        self.toplevels.borrow().iter()
+            .find(|t| t.activated)
+            .and_then(|t| t.outputs.first().cloned())
+    }
+
+    /// Currently open windows, sorted and without duplicates
+    pub fn windows(&self) -> Vec<Window> {
+        let mut ret: Vec<Window> = self.toplevels.borrow().iter()
+            .filter_map(|t| t.current.clone())
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+}
+
+impl Drop for WlrToplevels {
+    fn drop(&mut self) {
+        for toplevel in self.toplevels.borrow_mut().drain(..) {
+            toplevel.handle.destroy();
+        }
+        self.manager.stop();
+    }
+}
+
+/// Follows the output the focused window is on
+pub struct FocusTracker {
+    toplevels: WlrToplevels,
+    output: Option<WlOutput>,
+}
+
+impl FocusTracker {
+    pub fn new(toplevels: WlrToplevels) -> FocusTracker {
+        FocusTracker {
+            toplevels: toplevels,
+            output: None,
+        }
+    }
+
+    /// The output the focused window is on, if it changed since the last call. Losing focus
+    /// altogether doesn't count as a change.
+    pub fn poll(&mut self) -> Option<WlOutput> {
+        let focused = self.toplevels.focused_output()?;
+        let unchanged = self.output.as_ref()
+            .map(|output| output.as_ref().equals(focused.as_ref()))
+            .unwrap_or(false);
+        if unchanged {
+            return None;
+        }
+        self.output = Some(focused.clone());
+        Some(focused)
+    }
+}
+
+/// Windows reported through ext-foreign-toplevel-list, whose handles can be captured
+pub struct ExtToplevels {
+    list: Main<ExtForeignToplevelListV1>,
+    toplevels: Rc<RefCell<Vec<Toplevel<Main<ExtForeignToplevelHandleV1>>>>>,
+    /// Bumped whenever a window changes or closes
+    serial: Rc<Cell<u64>>,
+}
+
+impl ExtToplevels {
+    pub fn new(global_manager: &GlobalManager) -> Option<ExtToplevels> {
+        let list = global_manager.instantiate_exact::<ExtForeignToplevelListV1>(1).ok()?;
+        let toplevels = Rc::new(RefCell::new(Vec::new()));
+        let serial = Rc::new(Cell::new(0));
+        let handler_toplevels = Rc::downgrade(&toplevels);
+        let handler_serial = serial.clone();
+        list.assign_mono(move |_, evt| {
+            match evt {
+                ext_foreign_toplevel_list_v1::Event::Toplevel { toplevel } => {
+                    let toplevels = handler_toplevels.clone();
+                    let serial = handler_serial.clone();
+                    toplevel.assign_mono(move |handle, evt| {
+                        if ExtToplevels::handle_event(&toplevels, &handle, evt) {
+                            serial.set(serial.get() + 1);
+                        }
+                    });
+                    if let Some(toplevels) = handler_toplevels.upgrade() {
+                        toplevels.borrow_mut().push(Toplevel::new(toplevel));
+                    }
+                },
+                _ => {},
+            }
+        });
+        Some(ExtToplevels {
+            list: list,
+            toplevels: toplevels,
+            serial: serial,
+        })
+    }
+
+    /// Returns whether the list of windows changed
+    fn handle_event(toplevels: &Weak<RefCell<Vec<Toplevel<Main<ExtForeignToplevelHandleV1>>>>>, handle: &ExtForeignToplevelHandleV1, event: ext_foreign_toplevel_handle_v1::Event) -> bool {
+        use ext_foreign_toplevel_handle_v1::Event;
+        let toplevels = match toplevels.upgrade() {
+            Some(t) => t,
+            None => return false,
+        };
+        let mut toplevels = toplevels.borrow_mut();
+        let index = match toplevels.iter().position(|t| t.handle.as_ref().equals(handle.as_ref())) {
+            Some(i) => i,
+            None => return false,
+        };
+        match event {
+            Event::Title { title } => toplevels[index].pending.title = title,
+            Event::AppId { app_id } => toplevels[index].pending.app_id = app_id,
+            Event::Done => {
+                let toplevel = &mut toplevels[index];
+                toplevel.current = Some(toplevel.pending.clone());
+                return true;
+            },
+            Event::Closed => {
+                toplevels.remove(index).handle.destroy();
+                return true;
+            },
+            _ => {},
+        }
+        false
+    }
+
+    #[inline(always)]
+    pub fn serial(&self) -> u64 {
+        self.serial.get()
+    }
+
+    /// Currently open windows, sorted and without duplicates
+    pub fn windows(&self) -> Vec<Window> {
+        let mut ret: Vec<Window> = self.toplevels.borrow().iter()
+            .filter_map(|t| t.current.clone())
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    /// Handle of an open window equal to `window`
+    pub fn find(&self, window: &Window) -> Option<ExtForeignToplevelHandleV1> {
+        self.toplevels.borrow().iter()
+            .find(|t| t.current.as_ref() == Some(window))
+            .map(|t| (**t.handle).clone())
+    }
+}
+
+impl Drop for ExtToplevels {
+    fn drop(&mut self) {
+        for toplevel in self.toplevels.borrow_mut().drain(..) {
+            toplevel.handle.destroy();
+        }
+        self.list.destroy();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_setting_round_trips() {
+        let window = Window::new("org.gnome.Terminal", "~: vim src/main.rs");
+        assert_eq!(window.to_string().parse::<Window>(), Ok(window));
+        assert_eq!(":".parse::<Window>(), Ok(Window::default()));
+        assert!("firefox".parse::<Window>().is_err());
+    }
+
+    #[test]
+    fn labels_leave_out_missing_parts() {
+        assert_eq!(Window::new("firefox", "Meet").label(), "Meet (firefox)");
+        assert_eq!(Window::new("firefox", "").label(), "firefox");
+        assert_eq!(Window::new("", "Meet").label(), "Meet");
+    }
+
+    #[test]
+    fn exact_rule_matches_only_the_picked_window() {
+        let rule = WindowRule::exact(&Window::new("firefox", "Meet"));
+        assert!(rule.matches(&Window::new("firefox", "Meet")));
+        assert!(!rule.matches(&Window::new("firefox", "Meet - Firefox")));
+        assert!(!rule.matches(&Window::new("chromium", "Meet")));
+    }
+
+    #[test]
+    fn exact_patterns_match_whole_strings() {
+        let pattern = Pattern::Exact("firefox".into());
+        assert!(pattern.matches("firefox"));
+        assert!(!pattern.matches("firefox-esr"));
+        assert!(!pattern.matches("Firefox"));
+        // Regex syntax has no meaning in exact patterns
+        assert!(!Pattern::Exact("fire.*".into()).matches("firefox"));
+        assert!(Pattern::Exact("fire.*".into()).matches("fire.*"));
+    }
+
+    #[test]
+    fn regex_patterns_match_anywhere_unless_anchored() {
+        let pattern = Pattern::Regex(Regex::new("^Meet - ").unwrap());
+        assert!(pattern.matches("Meet - Standup"));
+        assert!(!pattern.matches("Zoom Meet - Standup"));
+        assert!(Pattern::Regex(Regex::new("Meet").unwrap()).matches("Zoom Meet - Standup"));
+    }
+
+    #[test]
+    fn rules_from_settings() {
+        assert_eq!(WindowRule::new("", "", false), Ok(None));
+        assert_eq!(WindowRule::new("", "", true), Ok(None));
+        assert_eq!(WindowRule::new("firefox", "", false), Ok(Some(WindowRule {
+            app_id: Some(Pattern::Exact("firefox".into())),
+            title: None,
+        })));
+        assert_eq!(WindowRule::new("", "^Meet", true), Ok(Some(WindowRule {
+            app_id: None,
+            title: Some(Pattern::Regex(Regex::new("^Meet").unwrap())),
+        })));
+        // Invalid regexes are only a problem when matching regexes
+        assert!(WindowRule::new("firefox", "Meet (", true).is_err());
+        assert!(WindowRule::new("firefox(", "", true).is_err());
+        assert!(WindowRule::new("firefox", "Meet (", false).unwrap().is_some());
+    }
+
+    #[test]
+    fn rules_match_every_pattern_they_have() {
+        let rule = WindowRule::new("firefox", "^Meet", true).unwrap().unwrap();
+        assert!(rule.matches(&Window::new("firefox", "Meet - Standup")));
+        assert!(rule.matches(&Window::new("firefox-esr", "Meet - Standup")));
+        assert!(!rule.matches(&Window::new("firefox", "Inbox")));
+        assert!(!rule.matches(&Window::new("chromium", "Meet - Standup")));
+
+        let rule = WindowRule::new("", "Meet", false).unwrap().unwrap();
+        assert!(rule.matches(&Window::new("firefox", "Meet")));
+        assert!(rule.matches(&Window::new("chromium", "Meet")));
+        assert!(!rule.matches(&Window::new("firefox", "Meet - Standup")));
+    }
+
+    #[test]
+    fn best_match_follows_rule_priority() {
+        let windows = [
+            Window::new("chromium", "Meet - Standup"),
+            Window::new("firefox", "Inbox"),
+            Window::new("firefox", "Meet - Standup"),
+        ];
+        let mut rules = WindowRules::default();
+        assert_eq!(rules.best_match(&windows), None);
+
+        rules.push(WindowRule::new("firefox", "^Meet", true).unwrap().unwrap());
+        rules.push(WindowRule::new("", "Meet", true).unwrap().unwrap());
+        // The first rule wins even though the second matches an earlier window
+        assert_eq!(rules.best_match(&windows), Some(&windows[2]));
+        // The second rule catches what's left once the first matches nothing
+        assert_eq!(rules.best_match(&windows[..2]), Some(&windows[0]));
+        // Among windows matching the same rule the first one wins
+        let mut any_firefox = WindowRules::default();
+        any_firefox.push(WindowRule::new("firefox", "", false).unwrap().unwrap());
+        assert_eq!(any_firefox.best_match(&windows), Some(&windows[1]));
+        assert_eq!(rules.best_match(&[Window::new("foot", "~")]), None);
+    }
+}
@@ -51,6 +51,9 @@ pub trait ObsData {
     fn get_string<S: AsRef<str>>(&self, key: S) -> Option<&ffi::CStr>;
     fn get_double<S: AsRef<str>>(&self, key: S) -> f64;
     fn get_bool<S: AsRef<str>>(&self, key: S) -> bool;
+    fn set_default_int<S: AsRef<str>>(&mut self, key: S, value: i64);
+    fn set_default_bool<S: AsRef<str>>(&mut self, key: S, value: bool);
+    fn set_default_string<S: AsRef<str>, V: AsRef<str>>(&mut self, key: S, value: V);
     fn clear(&mut self);
 
     fn get_str<S: AsRef<str>>(&self, key: S) -> Option<Cow<str>> {
@@ -104,6 +107,45 @@ impl ObsData for sys::obs_data_t {
         }
     }
 
+    fn set_default_int<S: AsRef<str>>(&mut self, key: S, value: i64) {
+        let key: &str = key.as_ref();
+        let c_key = ffi::CString::new(key)
+            .expect("Invalid utf8 key");
+        unsafe {
+            sys::obs_data_set_default_int(
+                self as *mut sys::obs_data_t,
+                mem::transmute(c_key.as_bytes_with_nul().as_ptr()),
+                value
+            )
+        }
+    }
+    fn set_default_bool<S: AsRef<str>>(&mut self, key: S, value: bool) {
+        let key: &str = key.as_ref();
+        let c_key = ffi::CString::new(key)
+            .expect("Invalid utf8 key");
+        unsafe {
+            sys::obs_data_set_default_bool(
+                self as *mut sys::obs_data_t,
+                mem::transmute(c_key.as_bytes_with_nul().as_ptr()),
+                value
+            )
+        }
+    }
+    fn set_default_string<S: AsRef<str>, V: AsRef<str>>(&mut self, key: S, value: V) {
+        let key: &str = key.as_ref();
+        let c_key = ffi::CString::new(key)
+            .expect("Invalid utf8 key");
+        let c_value = ffi::CString::new(value.as_ref())
+            .expect("Invalid utf8 value");
+        unsafe {
+            sys::obs_data_set_default_string(
+                self as *mut sys::obs_data_t,
+                mem::transmute(c_key.as_bytes_with_nul().as_ptr()),
+                mem::transmute(c_value.as_bytes_with_nul().as_ptr())
+            )
+        }
+    }
+
     fn clear(&mut self) {
         unsafe {
             sys::obs_data_clear(self as *mut sys::obs_data);
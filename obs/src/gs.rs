@@ -1,30 +1,131 @@
 pub struct Texture(*mut obs_sys::gs_texture_t);
 
+/// Holds the graphics context for as long as it is alive
+pub struct GraphicsContext(());
+
+impl GraphicsContext {
+    pub fn enter() -> GraphicsContext {
+        unsafe {
+            obs_sys::obs_enter_graphics();
+        }
+        GraphicsContext(())
+    }
+}
+
+impl Drop for GraphicsContext {
+    fn drop(&mut self) {
+        unsafe {
+            obs_sys::obs_leave_graphics();
+        }
+    }
+}
+
+/// Texture format for single-plane video formats, `None` for formats that need conversion
 #[allow(non_snake_case)]
-fn translate_format(fmt: obs_sys::video_format) -> obs_sys::gs_color_format {
+pub fn translate_format(fmt: obs_sys::video_format) -> Option<obs_sys::gs_color_format> {
     use obs_sys::video_format::*;
     use obs_sys::gs_color_format::*;
-    // TODO: complete this
     match fmt {
-        VIDEO_FORMAT_BGRA => GS_BGRA,
-        VIDEO_FORMAT_RGBA => GS_RGBA,
-        _ => panic!("Unknown video format: {:?}", &fmt),
+        VIDEO_FORMAT_BGRA => Some(GS_BGRA),
+        VIDEO_FORMAT_BGRX => Some(GS_BGRX),
+        VIDEO_FORMAT_RGBA => Some(GS_RGBA),
+        _ => None,
+    }
+}
+
+/// Size of a pixel in bytes for the uncompressed formats `translate_format` produces
+pub fn format_bytes_per_pixel(fmt: obs_sys::gs_color_format) -> Option<u32> {
+    use obs_sys::gs_color_format::*;
+    match fmt {
+        GS_BGRA | GS_BGRX | GS_RGBA => Some(4),
+        _ => None,
     }
 }
 
 impl Texture {
+    /// Creates an empty texture that is meant to be updated every frame
+    pub fn new_dynamic(width: u32, height: u32, color_format: obs_sys::gs_color_format) -> Option<Texture> {
+        let ptr = unsafe {
+            obs_sys::gs_texture_create(width, height, color_format, 1, std::ptr::null_mut(), obs_sys::GS_DYNAMIC)
+        };
+        if ptr.is_null() {
+            None
+        } else {
+            Some(Texture(ptr))
+        }
+    }
+
     #[inline(always)]
     pub fn as_raw(&mut self) -> *mut obs_sys::gs_texture_t {
         self.0
     }
-}
 
-impl<'a> From<&'a mut obs_sys::obs_source_frame> for Texture {
-    fn from(source_frame: &'a mut obs_sys::obs_source_frame) -> Texture {
-        let ptr = unsafe {
-            obs_sys::gs_texture_create(source_frame.width, source_frame.height, translate_format(source_frame.format), 1, source_frame.data.as_mut_ptr() as *mut *const u8, 0)
+    #[inline(always)]
+    pub fn width(&self) -> u32 {
+        unsafe {
+            obs_sys::gs_texture_get_width(self.0)
+        }
+    }
+
+    #[inline(always)]
+    pub fn height(&self) -> u32 {
+        unsafe {
+            obs_sys::gs_texture_get_height(self.0)
+        }
+    }
+
+    #[inline(always)]
+    pub fn color_format(&self) -> obs_sys::gs_color_format {
+        unsafe {
+            obs_sys::gs_texture_get_color_format(self.0)
+        }
+    }
+
+    /// Uploads a full image to a dynamic texture, `data` must hold `height` rows of `linesize` bytes
+    pub unsafe fn set_image(&mut self, data: *const u8, linesize: u32, invert: bool) {
+        obs_sys::gs_texture_set_image(self.0, data, linesize, invert);
+    }
+
+    /// Maps a dynamic texture for writing, it is unmapped when the returned value is dropped
+    pub fn map<'a>(&'a mut self) -> Option<MappedTexture<'a>> {
+        let mut ptr: *mut u8 = std::ptr::null_mut();
+        let mut linesize: u32 = 0;
+        let ok = unsafe {
+            obs_sys::gs_texture_map(self.0, &mut ptr, &mut linesize)
         };
-        Texture(ptr)
+        if ok && !ptr.is_null() {
+            Some(MappedTexture {
+                texture: self,
+                data: ptr,
+                linesize: linesize,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+pub struct MappedTexture<'a> {
+    texture: &'a mut Texture,
+    data: *mut u8,
+    linesize: u32,
+}
+
+impl<'a> MappedTexture<'a> {
+    /// Mutable view of row `y` of the texture, including any padding up to `linesize`
+    pub fn row_mut(&mut self, y: u32) -> &mut [u8] {
+        assert!(y < self.texture.height(), "row {} out of bounds", y);
+        unsafe {
+            std::slice::from_raw_parts_mut(self.data.offset((y * self.linesize) as isize), self.linesize as usize)
+        }
+    }
+}
+
+impl<'a> Drop for MappedTexture<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            obs_sys::gs_texture_unmap(self.texture.0);
+        }
     }
 }
 
@@ -37,3 +138,56 @@ impl Drop for Texture {
         }
     }
 }
+
+/// An image file decoded into memory by libobs, without creating a texture for it
+pub struct Image(obs_sys::gs_image_file_t);
+
+impl Image {
+    /// Loads and decodes the image at `path`. Only the first frame of animated images is kept.
+    pub fn load(path: &str) -> Option<Image> {
+        let path = std::ffi::CString::new(path).ok()?;
+        let mut image: obs_sys::gs_image_file_t = unsafe { std::mem::zeroed() };
+        unsafe {
+            obs_sys::gs_image_file_init(&mut image, path.as_ptr());
+        }
+        let ret = Image(image);
+        if ret.0.loaded && !ret.0.texture_data.is_null() && ret.0.cx > 0 && ret.0.cy > 0 {
+            Some(ret)
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    pub fn width(&self) -> u32 {
+        self.0.cx
+    }
+
+    #[inline(always)]
+    pub fn height(&self) -> u32 {
+        self.0.cy
+    }
+
+    #[inline(always)]
+    pub fn color_format(&self) -> obs_sys::gs_color_format {
+        self.0.format
+    }
+
+    /// Decoded pixels, `height` rows of `width` pixels in `color_format`
+    pub fn data(&self) -> &[u8] {
+        let bpp = format_bytes_per_pixel(self.0.format).unwrap_or(4) as usize;
+        unsafe {
+            std::slice::from_raw_parts(self.0.texture_data, (self.0.cx as usize) * (self.0.cy as usize) * bpp)
+        }
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        // Frees the (never created) texture too, which wants the graphics context
+        let _ctx = GraphicsContext::enter();
+        unsafe {
+            obs_sys::gs_image_file_free(&mut self.0);
+        }
+    }
+}
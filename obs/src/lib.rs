@@ -64,6 +64,24 @@ impl Into<u32> for SemanticVersion {
     }
 }
 
+/// Current time on the OBS clock, in nanoseconds
+#[inline(always)]
+pub fn os_gettime_ns() -> u64 {
+    unsafe {
+        sys::os_gettime_ns()
+    }
+}
+
+/// Base (canvas) resolution OBS renders at, `None` before video is initialized
+pub fn base_resolution() -> Option<(u32, u32)> {
+    let mut info: sys::obs_video_info = unsafe { std::mem::zeroed() };
+    let ok = unsafe {
+        sys::obs_get_video_info(&mut info)
+    };
+    Some((info.base_width, info.base_height))
+        .filter(|&(width, height)| ok && width != 0 && height != 0)
+}
+
 pub const fn libobs_api_ver() -> SemanticVersion {
     SemanticVersion::new(
         sys::LIBOBS_API_MAJOR_VER as u8,
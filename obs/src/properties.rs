@@ -22,6 +22,24 @@ impl Properties {
         &'a mut self,
         name: &str,
         description: &str
+    ) -> StringPropertyList<'a> {
+        self.add_string_combo(name, description, sys::obs_combo_type::OBS_COMBO_TYPE_LIST)
+    }
+
+    /// String list that also takes values typed in by the user
+    pub fn add_editable_string_list<'a>(
+        &'a mut self,
+        name: &str,
+        description: &str
+    ) -> StringPropertyList<'a> {
+        self.add_string_combo(name, description, sys::obs_combo_type::OBS_COMBO_TYPE_EDITABLE)
+    }
+
+    fn add_string_combo<'a>(
+        &'a mut self,
+        name: &str,
+        description: &str,
+        combo_type: sys::obs_combo_type
     ) -> StringPropertyList<'a> {
         let name = ffi::CString::new(name)
             .expect("invalid utf8 string");
@@ -32,13 +50,106 @@ impl Properties {
                 self.0,
                 mem::transmute(name.as_ptr()),
                 mem::transmute(description.as_ptr()),
-                sys::obs_combo_type::OBS_COMBO_TYPE_LIST,
+                combo_type,
                 sys::obs_combo_format::OBS_COMBO_FORMAT_STRING
             );
             StringPropertyList::from_raw(ptr)
         }
     }
 
+    pub fn add_bool(&mut self, name: &str, description: &str) {
+        let name = ffi::CString::new(name)
+            .expect("invalid utf8 string");
+        let description = ffi::CString::new(description)
+            .expect("invalid utf8 string");
+        unsafe {
+            sys::obs_properties_add_bool(
+                self.0,
+                mem::transmute(name.as_ptr()),
+                mem::transmute(description.as_ptr())
+            );
+        }
+    }
+
+    pub fn add_int(&mut self, name: &str, description: &str, min: i32, max: i32, step: i32) {
+        let name = ffi::CString::new(name)
+            .expect("invalid utf8 string");
+        let description = ffi::CString::new(description)
+            .expect("invalid utf8 string");
+        unsafe {
+            sys::obs_properties_add_int(
+                self.0,
+                mem::transmute(name.as_ptr()),
+                mem::transmute(description.as_ptr()),
+                min,
+                max,
+                step
+            );
+        }
+    }
+
+    pub fn add_text(&mut self, name: &str, description: &str) {
+        self.add_text_of_type(name, description, sys::obs_text_type::OBS_TEXT_DEFAULT);
+    }
+
+    /// Adds a color picker, its value is an int laid out as `0xAABBGGRR`
+    pub fn add_color(&mut self, name: &str, description: &str) {
+        let name = ffi::CString::new(name)
+            .expect("invalid utf8 string");
+        let description = ffi::CString::new(description)
+            .expect("invalid utf8 string");
+        unsafe {
+            sys::obs_properties_add_color(
+                self.0,
+                mem::transmute(name.as_ptr()),
+                mem::transmute(description.as_ptr())
+            );
+        }
+    }
+
+    /// Adds a file picker, `filter` as in Qt's file dialogs, e.g. `"Images (*.png *.jpg)"`
+    pub fn add_file_path(&mut self, name: &str, description: &str, filter: &str) {
+        let name = ffi::CString::new(name)
+            .expect("invalid utf8 string");
+        let description = ffi::CString::new(description)
+            .expect("invalid utf8 string");
+        let filter = ffi::CString::new(filter)
+            .expect("invalid utf8 string");
+        unsafe {
+            sys::obs_properties_add_path(
+                self.0,
+                mem::transmute(name.as_ptr()),
+                mem::transmute(description.as_ptr()),
+                sys::obs_path_type::OBS_PATH_FILE,
+                mem::transmute(filter.as_ptr()),
+                ptr::null()
+            );
+        }
+    }
+
+    /// Adds a read-only line of text, shown as the description of a disabled text property
+    pub fn add_info(&mut self, name: &str, text: &str) {
+        let ptr = self.add_text_of_type(name, text, sys::obs_text_type::OBS_TEXT_DEFAULT);
+        unsafe {
+            sys::obs_property_set_enabled(ptr, false);
+        }
+    }
+
+    fn add_text_of_type(&mut self, name: &str, description: &str, text_type: sys::obs_text_type) -> *mut sys::obs_property_t {
+        let name = ffi::CString::new(name)
+            .expect("invalid utf8 string");
+        let description = ffi::CString::new(description)
+            .expect("invalid utf8 string");
+        unsafe {
+            sys::obs_properties_add_text(
+                self.0,
+                mem::transmute(name.as_ptr()),
+                mem::transmute(description.as_ptr()),
+                text_type
+            )
+        }
+    }
+
     /// Unsafely create a properties object from a raw pointer
     pub unsafe fn from_raw(ptr: *mut sys::obs_properties_t) -> Properties {
         Properties(ptr)
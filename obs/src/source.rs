@@ -18,6 +18,31 @@ impl SourceHandle {
     pub fn as_raw(&self) -> *mut sys::obs_source_t {
         self.0
     }
+
+    /// Width OBS currently has for the source, for async sources the size of the last frame
+    #[inline(always)]
+    pub fn width(&self) -> u32 {
+        unsafe {
+            sys::obs_source_get_width(self.0)
+        }
+    }
+
+    #[inline(always)]
+    pub fn height(&self) -> u32 {
+        unsafe {
+            sys::obs_source_get_height(self.0)
+        }
+    }
+
+    /// Pushes a frame into the async video pipeline, `None` clears the current frame
+    pub fn output_video(&self, frame: Option<&sys::obs_source_frame>) {
+        let frame = frame
+            .map(|f| f as *const sys::obs_source_frame)
+            .unwrap_or(ptr::null());
+        unsafe {
+            sys::obs_source_output_video(self.0, frame);
+        }
+    }
 }
 
 unsafe impl Send for SourceHandle {}
@@ -79,10 +104,13 @@ pub trait Source: 'static + Sized {
     const NAME: &'static [u8];
     fn create(settings: &mut sys::obs_data_t, source: &mut sys::obs_source_t) -> Result<Self, String>;
 
+    fn get_defaults(_settings: &mut sys::obs_data_t) {}
     fn update(&mut self, _settings: &mut sys::obs_data_t) {}
     fn get_properties(&mut self) -> properties::Properties {
         properties::Properties::new()
     }
+    /// Called once per frame on the graphics thread
+    fn video_tick(&mut self, _seconds: f32) {}
 }
 
 unsafe extern "C" fn get_name<S: Source>(_data: *mut ffi::c_void) -> *const i8 {
@@ -102,6 +130,11 @@ unsafe extern "C" fn destroy<S: Source>(data: *mut ffi::c_void) {
     let _b: Box<S> = Box::from_raw(mem::transmute(data));
 }
 
+unsafe extern "C" fn get_defaults<S: Source>(settings: *mut sys::obs_data_t) {
+    let settings: &mut sys::obs_data_t = settings.as_mut().unwrap();
+    S::get_defaults(settings);
+}
+
 unsafe extern "C" fn update<S: Source>(data: *mut ffi::c_void, settings: *mut sys::obs_data_t) {
     let data: *mut S = mem::transmute(data);
     let data: &mut S = data.as_mut().unwrap();
@@ -115,6 +148,12 @@ unsafe extern "C" fn get_properties<S: Source>(data: *mut ffi::c_void) -> *mut s
     data.get_properties().into_raw()
 }
 
+unsafe extern "C" fn video_tick<S: Source>(data: *mut ffi::c_void, seconds: f32) {
+    let data: *mut S = mem::transmute(data);
+    let data: &mut S = data.as_mut().unwrap();
+    data.video_tick(seconds)
+}
+
 pub trait VideoSource: Source {
     fn width(&self) -> u32;
     fn height(&self) -> u32;
@@ -158,8 +197,10 @@ pub fn video_source_info<S: VideoSource>() -> SourceInfo {
     info.destroy = Some(destroy::<S>);
     info.get_width = Some(video_get_width::<S>);
     info.get_height = Some(video_get_height::<S>);
+    info.get_defaults = Some(get_defaults::<S>);
     info.update = Some(update::<S>);
     info.get_properties = Some(get_properties::<S>);
+    info.video_tick = Some(video_tick::<S>);
     info.video_render = Some(video_render::<S>);
     SourceInfo(info)
 }
@@ -172,8 +213,10 @@ pub fn async_video_source_info<S: AsyncVideoSource>() -> SourceInfo {
     info.get_name = Some(get_name::<S>);
     info.create = Some(create::<S>);
     info.destroy = Some(destroy::<S>);
+    info.get_defaults = Some(get_defaults::<S>);
     info.update = Some(update::<S>);
     info.get_properties = Some(get_properties::<S>);
+    info.video_tick = Some(video_tick::<S>);
     SourceInfo(info)
 }
 